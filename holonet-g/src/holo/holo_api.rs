@@ -1,22 +1,72 @@
+use std::collections::BTreeMap;
+
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::model::channel::Channel;
+use crate::model::message::Message;
+use crate::model::session::{Presence, Session};
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "type", content = "payload")]
 pub enum Output {
     #[serde(rename = "user-joined")]
     UserJoined(UserJoinedOutput),
+    #[serde(rename = "session-resumed")]
+    SessionResumed(SessionResumedOutput),
     #[serde(rename = "user-disconnect")]
     UserDisconnect(UserDiscconnectOutput),
     #[serde(rename = "user-message")]
     UserMessage(UserMessageOutput),
     #[serde(rename = "message")]
     Message(UserMessageOutput),
+    #[serde(rename = "channel-joined")]
+    ChannelJoined(ChannelJoinedOutput),
+    #[serde(rename = "channel-left")]
+    ChannelLeft(ChannelLeftOutput),
+    #[serde(rename = "channel-created")]
+    ChannelCreated(ChannelCreatedOutput),
+    #[serde(rename = "channel-waitlisted")]
+    ChannelWaitlisted(ChannelWaitlistedOutput),
+    #[serde(rename = "channel-list")]
+    ChannelList(ChannelListOutput),
+    #[serde(rename = "history")]
+    History(HistoryOutput),
+    #[serde(rename = "message-edited")]
+    MessageEdited(UserMessageOutput),
+    #[serde(rename = "message-deleted")]
+    MessageDeleted(MessageDeletedOutput),
+    #[serde(rename = "user-renamed")]
+    UserRenamed(UserRenamedOutput),
+    #[serde(rename = "direct-message")]
+    DirectMessage(DirectMessageOutput),
+    #[serde(rename = "user-list")]
+    UserList(UserListOutput),
+    #[serde(rename = "status-changed")]
+    StatusChanged(StatusChangedOutput),
+    #[serde(rename = "typing-started")]
+    TypingStarted(TypingOutput),
+    #[serde(rename = "typing-stopped")]
+    TypingStopped(TypingOutput),
+    #[serde(rename = "read-receipt")]
+    ReadReceipt(ReadReceiptOutput),
+    #[serde(rename = "unread-counts")]
+    UnreadCounts(UnreadCountsOutput),
+    #[serde(rename = "reaction-updated")]
+    ReactionUpdated(ReactionUpdatedOutput),
+    #[serde(rename = "thread")]
+    Thread(ThreadOutput),
+    #[serde(rename = "thread-updated")]
+    ThreadUpdated(ThreadUpdatedOutput),
+    #[serde(rename = "mentioned")]
+    Mentioned(UserMessageOutput),
+    #[serde(rename = "system-message")]
+    SystemMessage(SystemMessageOutput),
+    #[serde(rename = "ack")]
+    Ack,
     #[serde(rename = "error")]
     Error(ErrorOutput),
-    #[serde(rename = "keep-alive-tick")]
-    KeepAliveTick,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -26,9 +76,45 @@ pub enum Input {
     Join(JoinEvent),
     #[serde(rename = "message")]
     Message(MessageEvent),
+    #[serde(rename = "join-channel")]
+    JoinChannel(JoinChannelEvent),
+    #[serde(rename = "leave-channel")]
+    LeaveChannel(LeaveChannelEvent),
+    #[serde(rename = "create-channel")]
+    CreateChannel(CreateChannelEvent),
+    #[serde(rename = "list-channels")]
+    ListChannels(ListChannelsEvent),
+    #[serde(rename = "fetch-history")]
+    FetchHistory(FetchHistoryEvent),
+    #[serde(rename = "edit-message")]
+    EditMessage(EditMessageEvent),
+    #[serde(rename = "delete-message")]
+    DeleteMessage(DeleteMessageEvent),
+    #[serde(rename = "change-name")]
+    ChangeName(ChangeNameEvent),
+    #[serde(rename = "direct-message")]
+    DirectMessage(DirectMessageEvent),
+    #[serde(rename = "list-users")]
+    ListUsers(ListUsersEvent),
+    #[serde(rename = "set-status")]
+    SetStatus(SetStatusEvent),
+    #[serde(rename = "typing-start")]
+    TypingStart(TypingEvent),
+    #[serde(rename = "typing-stop")]
+    TypingStop(TypingEvent),
+    #[serde(rename = "mark-read")]
+    MarkRead(MarkReadEvent),
+    #[serde(rename = "add-reaction")]
+    AddReaction(ReactionEvent),
+    #[serde(rename = "remove-reaction")]
+    RemoveReaction(ReactionEvent),
+    #[serde(rename = "fetch-thread")]
+    FetchThread(FetchThreadEvent),
+    #[serde(rename = "fetch-unread")]
+    FetchUnread(FetchUnreadEvent),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "code")]
 pub enum ErrorOutput {
     #[serde(rename = "invalid-session")]
@@ -39,23 +125,72 @@ pub enum ErrorOutput {
     ChannelFull,
     #[serde(rename = "name-taken")]
     NameTaken,
+    #[serde(rename = "invalid-channel")]
+    InvalidChannel,
+    #[serde(rename = "invalid-channel-request")]
+    InvalidChannelRequest,
+    #[serde(rename = "message-not-found")]
+    MessageNotFound,
+    #[serde(rename = "forbidden")]
+    Forbidden,
+    #[serde(rename = "server-error")]
+    ServerError,
+    #[serde(rename = "invalid-name")]
+    InvalidName,
+    #[serde(rename = "resume-failed")]
+    ResumeFailed,
+    // The connection has already joined, a new name goes through change-name
+    #[serde(rename = "already-joined")]
+    AlreadyJoined,
+    // The frame couldn't be read as an input, `detail` says why
+    #[serde(rename = "invalid-input")]
+    InvalidInput { detail: String },
+    // Too many requests of this kind, the same request may be sent again after `retryAfterMs`
+    #[serde(rename = "rate-limited", rename_all = "camelCase")]
+    RateLimited { retry_after_ms: u64 },
+}
+
+// What a client sends over the socket, any input can carry a requestId that is echoed back on its replies
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InputEnvelope {
+    #[serde(flatten)]
+    pub body: Input,
+    #[serde(default)]
+    pub request_id: Option<String>,
+}
+
+// What the client receives, the requestId is only set on replies to the request that carried it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutputEnvelope<'a> {
+    #[serde(flatten)]
+    pub output: &'a Output,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<&'a str>,
 }
 
 #[derive(Debug, Clone)]
+// Inputs name their own channel where they need one, a session's channels are tracked by the Holocaster
 pub struct RequestPacket {
     pub session_id: Uuid,
-    pub channel_id: Uuid,
     pub body: Input,
+    pub request_id: Option<String>,
 }
- 
+
 impl RequestPacket {
-    pub fn new(session_id: Uuid, channel_id: Uuid, body: Input) -> Self {
+    pub fn new(session_id: Uuid, body: Input) -> Self {
         RequestPacket {
             session_id,
-            channel_id,
             body,
+            request_id: None,
         }
     }
+
+    pub fn with_request_id(mut self, request_id: Option<String>) -> Self {
+        self.request_id = request_id;
+        self
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -63,6 +198,7 @@ pub struct ResponsePacket {
     pub session_id: Uuid,
     pub channel_id: Uuid,
     pub output: Output,
+    pub request_id: Option<String>,
 }
 
 impl ResponsePacket {
@@ -71,8 +207,14 @@ impl ResponsePacket {
             session_id,
             channel_id,
             output,
+            request_id: None,
         }
     }
+
+    pub fn with_request_id(mut self, request_id: Option<String>) -> Self {
+        self.request_id = request_id;
+        self
+    }
 }
 
 // MODEL JSON IMPLMENETATION
@@ -81,6 +223,34 @@ impl ResponsePacket {
 pub struct UserModelResponse {
     pub id: Uuid,
     pub name: String,
+    pub status: Presence,
+}
+
+impl From<&Session> for UserModelResponse {
+    fn from(session: &Session) -> Self {
+        UserModelResponse {
+            id: session.id,
+            name: session.name.clone(),
+            status: session.status,
+        }
+    }
+}
+
+// Everyone currently in a channel, sorted by name
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelRosterResponse {
+    pub channel_id: Uuid,
+    pub members: Vec<UserModelResponse>,
+}
+
+impl ChannelRosterResponse {
+    pub fn new(channel_id: Uuid, members: Vec<UserModelResponse>) -> Self {
+        ChannelRosterResponse {
+            channel_id,
+            members,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -88,15 +258,65 @@ pub struct UserModelResponse {
 pub struct ChannelModelResponse {
     pub id: Uuid,
     pub name: String,
+    pub game_id: Uuid,
+    pub max_members: Option<usize>,
+    // Only set for the session's own channels, sent to that session alone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unread_count: Option<usize>,
+}
+
+impl ChannelModelResponse {
+    pub fn with_unread_count(mut self, unread_count: Option<usize>) -> Self {
+        self.unread_count = unread_count;
+        self
+    }
+}
+
+impl From<&Channel> for ChannelModelResponse {
+    fn from(channel: &Channel) -> Self {
+        ChannelModelResponse {
+            id: channel.id,
+            name: channel.name.clone(),
+            game_id: channel.game_id,
+            max_members: channel.max_members,
+            unread_count: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MessageModelResponse {
     pub id: Uuid,
+    // Increases by one for every message posted to the channel, a jump means messages were missed or deleted
+    pub seq: u64,
     pub body: String,
     pub created_by: Uuid,
     pub created_at: DateTime<Utc>,
+    pub edited_at: Option<DateTime<Utc>>,
+    // Emoji to the users that reacted with it
+    pub reactions: BTreeMap<String, Vec<Uuid>>,
+    // Set on replies, the message whose thread they belong to
+    pub parent_id: Option<Uuid>,
+    pub reply_count: u64,
+    pub mentions: Vec<Uuid>,
+}
+
+impl From<&Message> for MessageModelResponse {
+    fn from(message: &Message) -> Self {
+        MessageModelResponse {
+            id: message.id,
+            seq: message.seq,
+            body: message.body.clone(),
+            created_by: message.created_by,
+            created_at: message.created_at,
+            edited_at: message.edited_at,
+            reactions: reaction_map(message),
+            parent_id: message.parent_id,
+            reply_count: message.reply_count,
+            mentions: message.mentions.clone(),
+        }
+    }
 }
 
 // INCOMING EVENTS
@@ -112,7 +332,159 @@ pub struct JoinEvent {
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MessageEvent {
+    pub channel_id: Uuid,
+    pub body: String,
+    // Retrying with the same nonce never posts the message twice
+    #[serde(default)]
+    pub nonce: Option<String>,
+    // Post the message as a reply in this message's thread
+    #[serde(default)]
+    pub parent_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JoinChannelEvent {
+    pub channel_id: Uuid,
+    // Queue for a seat when the channel is full instead of failing with channel-full
+    #[serde(default)]
+    pub wait: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LeaveChannelEvent {
+    pub channel_id: Uuid,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateChannelEvent {
+    pub name: String,
+    // Optionally scope the channel to a single game
+    #[serde(default)]
+    pub game_id: Option<Uuid>,
+    // Overrides HolocasterConfig.max_channel_members for this channel
+    #[serde(default)]
+    pub max_members: Option<usize>,
+}
+
+// Every channel that can be joined, or only the ones scoped to `game_id`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListChannelsEvent {
+    #[serde(default)]
+    pub game_id: Option<Uuid>,
+}
+
+// Fetch a page of channel history, either before a message id or before a timestamp
+// When no cursor is given the most recent page is returned
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchHistoryEvent {
+    pub channel_id: Uuid,
+    #[serde(default)]
+    pub before_id: Option<Uuid>,
+    #[serde(default)]
+    pub before: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub before_seq: Option<u64>,
+    // Page forward from a sequence number instead, ex: to fill a gap
+    #[serde(default)]
+    pub after_seq: Option<u64>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+// Pages back through the replies to a message, newest page first
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchThreadEvent {
+    pub channel_id: Uuid,
+    pub message_id: Uuid,
+    #[serde(default)]
+    pub before_seq: Option<u64>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+// Unread counts for one of the session's channels, or all of them when channelId is left out
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchUnreadEvent {
+    #[serde(default)]
+    pub channel_id: Option<Uuid>,
+}
+
+// Only the author of a message may edit or delete it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EditMessageEvent {
+    pub channel_id: Uuid,
+    pub message_id: Uuid,
+    pub body: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteMessageEvent {
+    pub channel_id: Uuid,
+    pub message_id: Uuid,
+}
+
+// Goes through the same uniqueness check as joining
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeNameEvent {
+    pub user_name: String,
+}
+
+// Lists the members of one of the session's channels, or everyone online when channelId is left out
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListUsersEvent {
+    #[serde(default)]
+    pub channel_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetStatusEvent {
+    pub status: Presence,
+}
+
+// Clients repeat typing-start while the user keeps typing, the server stops it for them when they go quiet
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypingEvent {
+    pub channel_id: Uuid,
+}
+
+// Everything up to and including this message has been read
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarkReadEvent {
+    pub channel_id: Uuid,
+    pub message_id: Uuid,
+}
+
+// Reacting twice with the same emoji, or removing a reaction that isn't there, changes nothing
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReactionEvent {
+    pub channel_id: Uuid,
+    pub message_id: Uuid,
+    pub emoji: String,
+}
+
+// Addressed to a user instead of a channel, only the sender and the recipient ever see it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectMessageEvent {
+    pub user_id: Uuid,
     pub body: String,
+    #[serde(default)]
+    pub nonce: Option<String>,
 }
 
 // OUTGOING EVENTS
@@ -123,16 +495,58 @@ pub struct MessageEvent {
 pub struct UserJoinedOutput {
     pub channels: Vec<ChannelModelResponse>,
     pub user: UserModelResponse,
+    // Only ever sent to the user that joined, see Holocaster::connect
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resume_token: Option<String>,
+    // Who is already in each of the channels, also only sent to the user that joined
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rosters: Vec<ChannelRosterResponse>,
 }
 
 impl UserJoinedOutput {
+    pub fn new(channels: Vec<ChannelModelResponse>, user: UserModelResponse) -> Self {
+        UserJoinedOutput {
+            channels,
+            user,
+            resume_token: None,
+            rosters: Vec::new(),
+        }
+    }
+
+    pub fn with_rosters(mut self, rosters: Vec<ChannelRosterResponse>) -> Self {
+        self.rosters = rosters;
+        self
+    }
+
+    pub fn with_resume_token(mut self, resume_token: Option<String>) -> Self {
+        self.resume_token = resume_token;
+        self
+    }
+}
+
+// Sent first on a connection that reattached to its session, followed by everything it missed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionResumedOutput {
+    pub channels: Vec<ChannelModelResponse>,
+    pub user: UserModelResponse,
+    // Resume tokens are single use, reconnect with this one next time
+    pub resume_token: String,
+    pub rosters: Vec<ChannelRosterResponse>,
+}
+
+impl SessionResumedOutput {
     pub fn new(
         channels: Vec<ChannelModelResponse>,
         user: UserModelResponse,
+        resume_token: String,
+        rosters: Vec<ChannelRosterResponse>,
     ) -> Self {
-        UserJoinedOutput {
+        SessionResumedOutput {
             channels,
             user,
+            resume_token,
+            rosters,
         }
     }
 }
@@ -162,6 +576,317 @@ impl UserMessageOutput {
     }
 }
 
+// Generated anytime a user joins a channel after registering their session
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelJoinedOutput {
+    pub channel: ChannelModelResponse,
+    pub user: UserModelResponse,
+}
+
+impl ChannelJoinedOutput {
+    pub fn new(channel: ChannelModelResponse, user: UserModelResponse) -> Self {
+        ChannelJoinedOutput { channel, user }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelLeftOutput {
+    pub channel_id: Uuid,
+    pub user_id: Uuid,
+}
+
+impl ChannelLeftOutput {
+    pub fn new(channel_id: Uuid, user_id: Uuid) -> Self {
+        ChannelLeftOutput {
+            channel_id,
+            user_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelCreatedOutput {
+    pub channel: ChannelModelResponse,
+}
+
+impl ChannelCreatedOutput {
+    pub fn new(channel: ChannelModelResponse) -> Self {
+        ChannelCreatedOutput { channel }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelListOutput {
+    pub channels: Vec<ChannelModelResponse>,
+}
+
+impl ChannelListOutput {
+    pub fn new(channels: Vec<ChannelModelResponse>) -> Self {
+        ChannelListOutput { channels }
+    }
+}
+
+// Sent when a full channel queues the session, channel-joined follows once a seat opens up
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelWaitlistedOutput {
+    pub channel: ChannelModelResponse,
+    // 1-based place in the waitlist
+    pub position: usize,
+}
+
+impl ChannelWaitlistedOutput {
+    pub fn new(channel: ChannelModelResponse, position: usize) -> Self {
+        ChannelWaitlistedOutput { channel, position }
+    }
+}
+
+// A page of channel history, oldest message first
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryOutput {
+    pub channel: ChannelModelResponse,
+    pub messages: Vec<MessageModelResponse>,
+    // True when there are more messages past this page, older ones unless the request paged forward with afterSeq
+    pub has_more: bool,
+}
+
+impl HistoryOutput {
+    pub fn new(
+        channel: ChannelModelResponse,
+        messages: Vec<MessageModelResponse>,
+        has_more: bool,
+    ) -> Self {
+        HistoryOutput {
+            channel,
+            messages,
+            has_more,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThreadOutput {
+    pub channel_id: Uuid,
+    pub parent: MessageModelResponse,
+    pub replies: Vec<MessageModelResponse>,
+    // True when there are older replies than this page
+    pub has_more: bool,
+}
+
+impl ThreadOutput {
+    pub fn new(
+        channel_id: Uuid,
+        parent: MessageModelResponse,
+        replies: Vec<MessageModelResponse>,
+        has_more: bool,
+    ) -> Self {
+        ThreadOutput {
+            channel_id,
+            parent,
+            replies,
+            has_more,
+        }
+    }
+}
+
+// Text from the server rather than a user, such as the result of a slash command
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SystemMessageOutput {
+    pub channel_id: Uuid,
+    pub body: String,
+}
+
+impl SystemMessageOutput {
+    pub fn new(channel_id: Uuid, body: String) -> Self {
+        SystemMessageOutput { channel_id, body }
+    }
+}
+
+// Sent to the channel whenever a reply is posted to or deleted from a thread
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThreadUpdatedOutput {
+    pub channel_id: Uuid,
+    pub message_id: Uuid,
+    pub reply_count: u64,
+}
+
+impl ThreadUpdatedOutput {
+    pub fn new(parent: &Message) -> Self {
+        ThreadUpdatedOutput {
+            channel_id: parent.channel_id,
+            message_id: parent.id,
+            reply_count: parent.reply_count,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageDeletedOutput {
+    pub channel_id: Uuid,
+    pub message_id: Uuid,
+}
+
+impl MessageDeletedOutput {
+    pub fn new(channel_id: Uuid, message_id: Uuid) -> Self {
+        MessageDeletedOutput {
+            channel_id,
+            message_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserRenamedOutput {
+    pub user: UserModelResponse,
+    pub previous_name: String,
+}
+
+impl UserRenamedOutput {
+    pub fn new(user: UserModelResponse, previous_name: String) -> Self {
+        UserRenamedOutput {
+            user,
+            previous_name,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserListOutput {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_id: Option<Uuid>,
+    pub users: Vec<UserModelResponse>,
+}
+
+impl UserListOutput {
+    pub fn new(channel_id: Option<Uuid>, users: Vec<UserModelResponse>) -> Self {
+        UserListOutput { channel_id, users }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusChangedOutput {
+    pub user: UserModelResponse,
+}
+
+impl StatusChangedOutput {
+    pub fn new(user: UserModelResponse) -> Self {
+        StatusChangedOutput { user }
+    }
+}
+
+// Relayed to the other members of the channel, typing is never stored
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypingOutput {
+    pub channel_id: Uuid,
+    pub user_id: Uuid,
+}
+
+impl TypingOutput {
+    pub fn new(channel_id: Uuid, user_id: Uuid) -> Self {
+        TypingOutput {
+            channel_id,
+            user_id,
+        }
+    }
+}
+
+// The session's channels with their unread counts, the reply to mark-read and fetch-unread
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnreadCountsOutput {
+    pub channels: Vec<ChannelModelResponse>,
+}
+
+impl UnreadCountsOutput {
+    pub fn new(channels: Vec<ChannelModelResponse>) -> Self {
+        UnreadCountsOutput { channels }
+    }
+}
+
+// How far a member has read, only relayed when the server broadcasts read receipts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadReceiptOutput {
+    pub channel_id: Uuid,
+    pub user_id: Uuid,
+    pub message_id: Uuid,
+    pub seq: u64,
+}
+
+impl ReadReceiptOutput {
+    pub fn new(channel_id: Uuid, user_id: Uuid, message_id: Uuid, seq: u64) -> Self {
+        ReadReceiptOutput {
+            channel_id,
+            user_id,
+            message_id,
+            seq,
+        }
+    }
+}
+
+// Carries every reaction on the message after `userId` added or removed `emoji`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReactionUpdatedOutput {
+    pub channel_id: Uuid,
+    pub message_id: Uuid,
+    pub user_id: Uuid,
+    pub emoji: String,
+    pub reactions: BTreeMap<String, Vec<Uuid>>,
+}
+
+impl ReactionUpdatedOutput {
+    pub fn new(message: &Message, user_id: Uuid, emoji: String) -> Self {
+        ReactionUpdatedOutput {
+            channel_id: message.channel_id,
+            message_id: message.id,
+            user_id,
+            emoji,
+            reactions: reaction_map(message),
+        }
+    }
+}
+
+// Sent to both sides of a direct message, the conversationId pages through their history with fetch-history
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectMessageOutput {
+    pub message: MessageModelResponse,
+    pub conversation_id: Uuid,
+    pub recipient_id: Uuid,
+}
+
+impl DirectMessageOutput {
+    pub fn new(message: MessageModelResponse, conversation_id: Uuid, recipient_id: Uuid) -> Self {
+        DirectMessageOutput {
+            message,
+            conversation_id,
+            recipient_id,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ErrorEvent {}
+
+fn reaction_map(message: &Message) -> BTreeMap<String, Vec<Uuid>> {
+    message
+        .reactions
+        .iter()
+        .map(|(emoji, users)| (emoji.clone(), users.iter().copied().collect()))
+        .collect()
+}
@@ -0,0 +1,60 @@
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::holo::holo_errors::{Error, Result};
+use crate::model::session::is_valid_user_name;
+
+// Sessions are only accepted with a token signed by our game servers
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthConfig {
+    // Shared HMAC secret used to sign session tokens (HS256)
+    pub secret: String,
+}
+
+// The claims our game servers put in a session token
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Claims {
+    // The game user id, this becomes the Holonet session id
+    pub sub: Uuid,
+    // Display name shown in chat
+    pub name: String,
+    // Expiry as a unix timestamp, expired tokens are rejected
+    pub exp: usize,
+}
+
+// Who an authenticated connection belongs to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    pub user_id: Uuid,
+    pub name: String,
+}
+
+impl From<Claims> for Identity {
+    fn from(claims: Claims) -> Self {
+        Identity {
+            user_id: claims.sub,
+            name: claims.name,
+        }
+    }
+}
+
+// Verify the token signature and expiry, and derive the identity from its claims
+// The name has to pass the same checks as a join, a token with a name that can't join is refused up front
+pub fn authenticate(config: &AuthConfig, token: &str) -> Result<Identity> {
+    let mut claims = decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(config.secret.as_bytes()),
+        &Validation::new(Algorithm::HS256),
+    )?
+    .claims;
+
+    claims.name = String::from(claims.name.trim());
+    if !is_valid_user_name(&claims.name) {
+        return Err(Error::System(format!(
+            "invalid name claim {:?}",
+            claims.name
+        )));
+    }
+    Ok(Identity::from(claims))
+}
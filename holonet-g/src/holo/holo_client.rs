@@ -1,87 +1,157 @@
 // use std::{error, result};
 
-use futures::stream::SplitStream;
-use futures::Stream;
+use std::error;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use futures::stream::{SplitSink, SplitStream};
+use futures::{SinkExt, Stream};
 use tokio_stream::StreamExt;
 // use tokio::time;
 use uuid::Uuid;
 // use warp::filters::ws::WebSocket;
 
-use crate::holo::holo_api::{Input, RequestPacket, ResponsePacket};
+use crate::holo::holo_api::{Input, InputEnvelope, OutputEnvelope, RequestPacket};
+use crate::holo::holo_auth::Identity;
 use crate::holo::holo_errors::{Error, Result};
+use crate::holo::holo_outbox::{Outbox, Outgoing};
 
 #[derive(Clone, Default)]
 pub struct HoloClient {
     // This is the session UUID
     pub id: Uuid,
-    // a client can belong to multiple channels at once!
-    pub channels: Vec<Uuid>,
+    // Set when the connection was authenticated with a session token
+    pub identity: Option<Identity>,
+    // Where the connection came from, when the server could tell
+    pub remote: Option<IpAddr>,
 }
 
 impl HoloClient {
     // TODO: we can add all sorts of cool stuff in here
-    pub fn new(channels: Vec<Uuid>) -> Self {
-        // TODO: we should store the session header JWT token here as well for quick lookup downstream?
+    pub fn new() -> Self {
         HoloClient {
             id: Uuid::new_v4(),
-            channels,
+            identity: None,
+            remote: None,
+        }
+    }
+
+    // An authenticated client uses the user id from its session token as the session id
+    pub fn with_identity(identity: Identity) -> Self {
+        HoloClient {
+            id: identity.user_id,
+            identity: Some(identity),
+            remote: None,
         }
     }
 
+    pub fn with_remote(mut self, remote: Option<IpAddr>) -> Self {
+        self.remote = remote;
+        self
+    }
+
     // TODO: make this channel specific
     pub fn handle_incoming(
         &self,
         stream: SplitStream<warp::ws::WebSocket>,
+        outbox: Arc<Outbox>,
     ) -> impl Stream<Item = Result<RequestPacket>> {
         let session_id = self.id;
+        let identity = self.identity.clone();
 
         println!("Attempting to handle na incoming connect!!");
 
         stream
-            .take_while(|message| {
-                if let Ok(message) = message {
-                    message.is_text()
-                } else {
+            .take_while(move |message| match message {
+                Ok(message) => {
+                    outbox.touch();
+                    !message.is_close()
+                }
+                // Let an oversized frame through so the connection can be closed with a reason
+                Err(err) if is_capacity_error(err) => true,
+                Err(_) => {
                     println!("!! nope validation failed, aborting !!");
                     false
                 }
             })
+            // Pings and pongs only matter for liveness
+            .filter(|message| {
+                message
+                    .as_ref()
+                    .map_or(true, |message| !message.is_ping() && !message.is_pong())
+            })
             .map(move |message| match message {
+                Err(err) if is_capacity_error(&err) => Err(Error::FrameTooLarge),
                 Err(err) => {
                     println!("!! had a really big error !!");
                     Err(Error::System(err.to_string()))
                 }
                 Ok(message) => {
-                    println!("!! message was good! {}", message.to_str().unwrap());
-                    let body: Input = serde_json::from_str(message.to_str().unwrap())?;
+                    let text = message.to_str().map_err(|_| {
+                        Error::InvalidInput(String::from("only text frames are supported"))
+                    })?;
+                    println!("!! message was good! {}", text);
+                    let InputEnvelope {
+                        mut body,
+                        request_id,
+                    } = serde_json::from_str(text)
+                        .map_err(|err| Error::InvalidInput(err.to_string()))?;
+                    // Authenticated clients join under the name from their token, change-name is refused later on
+                    if let (Some(identity), Input::Join(join)) = (&identity, &mut body) {
+                        join.user_name = identity.name.clone();
+                    }
                     println!("!! Sending response back to client !!");
-                    // TODO: the second param should be a channel id
-                    // Using the session ID so I can test the code flow
-                    Ok(RequestPacket::new(session_id, session_id, body))
+                    Ok(RequestPacket::new(session_id, body).with_request_id(request_id))
                 }
             })
     }
 
+    // Drain the session's outbox into the socket, waiting on the socket applies backpressure to the outbox
     pub async fn write_output(
         &self,
-        mut reciever: tokio::sync::broadcast::Receiver<ResponsePacket>,
-        stream: tokio::sync::mpsc::UnboundedSender<
-            std::result::Result<warp::ws::Message, warp::Error>,
-        >,
+        outbox: &Outbox,
+        mut sink: SplitSink<warp::ws::WebSocket, warp::ws::Message>,
     ) {
-        let session_id = self.id;
         println!("Attempting to write output!!!");
 
-        // 
-        while let Some(result) = reciever.recv().await.ok() {
-            if result.session_id == session_id {
-                println!("got the message to send {}", result.session_id);
-                let data = serde_json::to_string(&result.output).unwrap();
-                let msg = warp::ws::Message::text(data);
-                stream.send(Ok(msg)).unwrap();
+        while let Some(item) = outbox.recv().await {
+            // Batch up whatever else is already queued so we only flush once
+            let mut batch = vec![item];
+            while let Some(item) = outbox.try_recv() {
+                batch.push(item);
+            }
+
+            let closing = batch.iter().any(|item| matches!(item, Outgoing::Close(..)));
+            let mut messages =
+                futures::stream::iter(batch.into_iter().map(|item| Ok(to_message(item))));
+            if let Err(err) = sink.send_all(&mut messages).await {
+                println!("Failed to write to session {}: {}", self.id, err);
+                return;
+            }
+            if closing {
+                return;
             }
         }
+    }
+}
 
-        return;
+fn to_message(item: Outgoing) -> warp::ws::Message {
+    match item {
+        Outgoing::Packet(packet) => {
+            let envelope = OutputEnvelope {
+                output: &packet.output,
+                request_id: packet.request_id.as_deref(),
+            };
+            warp::ws::Message::text(serde_json::to_string(&envelope).unwrap())
+        }
+        Outgoing::Ping => warp::ws::Message::ping(Vec::new()),
+        Outgoing::Close(code, reason) => warp::ws::Message::close_with(code, reason),
     }
 }
+
+// tungstenite refuses frames over the configured size with a capacity error, warp only hands it over boxed
+fn is_capacity_error(err: &warp::Error) -> bool {
+    error::Error::source(err)
+        .and_then(|source| source.downcast_ref::<tungstenite::Error>())
+        .is_some_and(|err| matches!(err, tungstenite::Error::Capacity(_)))
+}
@@ -0,0 +1,460 @@
+use std::time::Duration;
+
+use futures::StreamExt;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::Mutex;
+use tokio::time;
+use uuid::Uuid;
+
+use crate::holo::holo_api::Output;
+use crate::holo::holo_errors::{Error, Result};
+use crate::model::channel::Channel;
+use crate::model::message::Message;
+use crate::model::session::Session;
+use crate::store::{HoloStore, StoreChange};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+// Hands out the next seq for a channel, never below the floor the calling node already has
+// The floor only matters the first time, for history stored before the node joined the cluster
+const NEXT_SEQ_SCRIPT: &str = r"
+local seq = redis.call('INCR', KEYS[1])
+local floor = tonumber(ARGV[1])
+if seq < floor then
+    redis.call('SET', KEYS[1], floor)
+    seq = floor
+end
+return seq
+";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClusterConfig {
+    // ex: redis://127.0.0.1:6379
+    pub redis_url: String,
+    // The Redis pub/sub channel every node in the cluster publishes to and listens on
+    pub topic: String,
+    // Number messages from a counter kept in Redis, this needs Lua scripting so servers with only pub/sub leave it off
+    // Without it every node numbers its own messages, two nodes posting to a channel at once can hand out the same seq
+    pub shared_seq: bool,
+}
+
+// Who an output is meant for, every node resolves this against its own local sessions
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Audience {
+    #[serde(rename_all = "camelCase")]
+    AllExcept { session_id: Uuid },
+    #[serde(rename_all = "camelCase")]
+    Session { session_id: Uuid },
+    #[serde(rename_all = "camelCase")]
+    Channel { channel_id: Uuid },
+    #[serde(rename_all = "camelCase")]
+    ChannelExcept { channel_id: Uuid, session_id: Uuid },
+}
+
+impl Audience {
+    pub fn includes(&self, session: &Session) -> bool {
+        match *self {
+            Audience::AllExcept { session_id } => session.id != session_id,
+            Audience::Session { session_id } => session.id == session_id,
+            Audience::Channel { channel_id } => session.is_member(channel_id),
+            Audience::ChannelExcept {
+                channel_id,
+                session_id,
+            } => session.id != session_id && session.is_member(channel_id),
+        }
+    }
+
+    // The channel the output belongs to, or a nil UUID when it isn't channel specific
+    pub fn channel_id(&self) -> Uuid {
+        match *self {
+            Audience::Channel { channel_id } | Audience::ChannelExcept { channel_id, .. } => {
+                channel_id
+            }
+            _ => Uuid::nil(),
+        }
+    }
+}
+
+// What travels over Redis between Holonet nodes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterEvent {
+    // The node that published the event, used so a node ignores its own echo
+    pub node_id: Uuid,
+    pub message: ClusterMessage,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload")]
+pub enum ClusterMessage {
+    // Replayed to the node's own sessions in the audience
+    #[serde(rename = "output")]
+    Output { audience: Audience, output: Output },
+    // Applied to the node's own store, so history is the same on every node
+    #[serde(rename = "store-change")]
+    StoreChange(StoreChange),
+}
+
+// Queues messages for the other nodes, this never waits on Redis
+#[derive(Clone)]
+pub struct ClusterPublisher {
+    node_id: Uuid,
+    sender: UnboundedSender<ClusterEvent>,
+}
+
+impl ClusterPublisher {
+    pub fn publish(&self, message: ClusterMessage) {
+        let event = ClusterEvent {
+            node_id: self.node_id,
+            message,
+        };
+        if self.sender.send(event).is_err() {
+            println!("Cluster publisher is not running, dropping event!");
+        }
+    }
+}
+
+// Fans outputs and store changes out to every other Holonet node through Redis pub/sub
+pub struct HoloCluster {
+    node_id: Uuid,
+    topic: String,
+    shared_seq: bool,
+    client: redis::Client,
+    publisher: ClusterPublisher,
+    outgoing_receiver: Mutex<Option<UnboundedReceiver<ClusterEvent>>>,
+    // Shared by every seq request, reconnected after an error
+    connection: Mutex<Option<redis::aio::MultiplexedConnection>>,
+    next_seq_script: redis::Script,
+}
+
+impl HoloCluster {
+    pub fn new(config: ClusterConfig) -> Result<Self> {
+        let client = redis::Client::open(config.redis_url.as_str())?;
+        let (outgoing_sender, outgoing_receiver) = mpsc::unbounded_channel();
+        let node_id = Uuid::new_v4();
+
+        Ok(HoloCluster {
+            node_id,
+            topic: config.topic,
+            shared_seq: config.shared_seq,
+            client,
+            publisher: ClusterPublisher {
+                node_id,
+                sender: outgoing_sender,
+            },
+            outgoing_receiver: Mutex::new(Some(outgoing_receiver)),
+            connection: Mutex::new(None),
+            next_seq_script: redis::Script::new(NEXT_SEQ_SCRIPT),
+        })
+    }
+
+    pub fn node_id(&self) -> Uuid {
+        self.node_id
+    }
+
+    // Whether seqs come from next_seq or from each node's own store
+    pub fn shares_seq(&self) -> bool {
+        self.shared_seq
+    }
+
+    pub fn publisher(&self) -> ClusterPublisher {
+        self.publisher.clone()
+    }
+
+    // Queue an output for the other nodes, this never waits on Redis
+    pub fn publish(&self, audience: Audience, output: Output) {
+        self.publisher
+            .publish(ClusterMessage::Output { audience, output });
+    }
+
+    // The next seq for a channel from a counter every node shares, so two nodes never number two messages the same
+    // `floor` is the seq this node would have used on its own
+    pub async fn next_seq(&self, channel_id: Uuid, floor: u64) -> Result<u64> {
+        let mut connection = self.connection.lock().await;
+        if connection.is_none() {
+            *connection = Some(self.client.get_multiplexed_async_connection().await?);
+        }
+        // The lock is let go before the round trip, multiplexed connections are cheap to clone
+        let mut multiplexed = match connection.clone() {
+            Some(multiplexed) => multiplexed,
+            None => return Err(Error::System(String::from("no cluster connection"))),
+        };
+        drop(connection);
+
+        let key = format!("{}:seq:{}", self.topic, channel_id);
+        let mut invocation = self.next_seq_script.key(key);
+        invocation.arg(floor);
+        match invocation.invoke_async(&mut multiplexed).await {
+            Ok(seq) => Ok(seq),
+            Err(err) => {
+                *self.connection.lock().await = None;
+                Err(err.into())
+            }
+        }
+    }
+
+    // Publish queued events and hand events from other nodes to `deliver`
+    // Redis outages are logged and retried, this only returns if it is run twice
+    pub async fn run<F, Fut>(&self, deliver: F)
+    where
+        F: Fn(ClusterEvent) -> Fut,
+        Fut: std::future::Future<Output = ()>,
+    {
+        let outgoing = match self.outgoing_receiver.lock().await.take() {
+            Some(outgoing) => outgoing,
+            None => {
+                println!("Cluster node {} is already running!", self.node_id);
+                return;
+            }
+        };
+
+        println!("Cluster node {} joining topic {}", self.node_id, self.topic);
+        tokio::join!(self.run_publisher(outgoing), self.run_subscriber(deliver));
+    }
+
+    async fn run_publisher(&self, mut outgoing: UnboundedReceiver<ClusterEvent>) {
+        let mut connection = None;
+        while let Some(event) = outgoing.recv().await {
+            let payload = match serde_json::to_string(&event) {
+                Ok(payload) => payload,
+                Err(err) => {
+                    println!("Failed to serialize cluster event: {}", err);
+                    continue;
+                }
+            };
+
+            if connection.is_none() {
+                connection = match self.client.get_async_connection().await {
+                    Ok(connection) => Some(connection),
+                    Err(err) => {
+                        println!("Failed to connect cluster publisher: {}", err);
+                        None
+                    }
+                };
+            }
+
+            // Events that can't be published are dropped, other nodes only miss live traffic
+            if let Some(publisher) = connection.as_mut() {
+                let published: redis::RedisResult<()> =
+                    publisher.publish(&self.topic, payload).await;
+                if let Err(err) = published {
+                    println!("Failed to publish cluster event: {}", err);
+                    connection = None;
+                }
+            }
+        }
+    }
+
+    async fn run_subscriber<F, Fut>(&self, deliver: F)
+    where
+        F: Fn(ClusterEvent) -> Fut,
+        Fut: std::future::Future<Output = ()>,
+    {
+        loop {
+            match self.subscribe(&deliver).await {
+                Ok(()) => println!("Cluster subscription closed, reconnecting!"),
+                Err(err) => println!("Cluster subscription failed: {}", err),
+            }
+            time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+
+    async fn subscribe<F, Fut>(&self, deliver: &F) -> Result<()>
+    where
+        F: Fn(ClusterEvent) -> Fut,
+        Fut: std::future::Future<Output = ()>,
+    {
+        let mut pubsub = self.client.get_async_connection().await?.into_pubsub();
+        pubsub.subscribe(&self.topic).await?;
+
+        let mut messages = pubsub.on_message();
+        while let Some(message) = messages.next().await {
+            let event = message
+                .get_payload::<String>()
+                .map_err(Error::from)
+                .and_then(|payload| Ok(serde_json::from_str::<ClusterEvent>(&payload)?));
+
+            match event {
+                // Redis echoes our own publications back to us
+                Ok(event) if event.node_id == self.node_id => {}
+                Ok(event) => deliver(event).await,
+                Err(err) => println!("Skipping unreadable cluster event: {}", err),
+            }
+        }
+        Ok(())
+    }
+}
+
+// Publishes every write to the other nodes, writes they publish come back in through `apply` and stop there
+pub struct ReplicatedStore {
+    inner: Box<dyn HoloStore>,
+    publisher: ClusterPublisher,
+}
+
+impl ReplicatedStore {
+    pub fn new(inner: Box<dyn HoloStore>, publisher: ClusterPublisher) -> Self {
+        ReplicatedStore { inner, publisher }
+    }
+
+    fn replicate(&self, change: StoreChange) {
+        self.publisher.publish(ClusterMessage::StoreChange(change));
+    }
+}
+
+// Sessions belong to the node their connection is on, they are never replicated
+impl HoloStore for ReplicatedStore {
+    fn sessions(&self) -> Box<dyn Iterator<Item = &Session> + '_> {
+        self.inner.sessions()
+    }
+
+    fn session_get(&self, session_id: Uuid) -> Option<&Session> {
+        self.inner.session_get(session_id)
+    }
+
+    fn session_save(&mut self, session: Session) -> Result<()> {
+        self.inner.session_save(session)
+    }
+
+    fn session_remove(&mut self, session_id: Uuid) -> Result<Option<Session>> {
+        self.inner.session_remove(session_id)
+    }
+
+    fn channels(&self) -> Box<dyn Iterator<Item = &Channel> + '_> {
+        self.inner.channels()
+    }
+
+    fn channel_get(&self, channel_id: Uuid) -> Option<&Channel> {
+        self.inner.channel_get(channel_id)
+    }
+
+    fn channel_save(&mut self, channel: Channel) -> Result<()> {
+        self.inner.channel_save(channel.clone())?;
+        self.replicate(StoreChange::ChannelSaved(channel));
+        Ok(())
+    }
+
+    fn message_save(&mut self, message: Message) -> Result<()> {
+        self.inner.message_save(message.clone())?;
+        self.replicate(StoreChange::MessageSaved(message));
+        Ok(())
+    }
+
+    fn message_delete(&mut self, channel_id: Uuid, message_id: Uuid) -> Result<Option<Message>> {
+        let removed = self.inner.message_delete(channel_id, message_id)?;
+        if removed.is_some() {
+            self.replicate(StoreChange::MessageDeleted {
+                channel_id,
+                message_id,
+            });
+        }
+        Ok(removed)
+    }
+
+    fn apply(&mut self, change: StoreChange) -> Result<()> {
+        self.inner.apply(change)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::{json, Value};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::holo::holo_api::{Input, RequestPacket};
+    use crate::holo::holo_client::HoloClient;
+    use crate::holo::holo_outbox::{Outbox, Outgoing};
+    use crate::holo::holocaster::{Holocaster, HolocasterConfig};
+
+    // A node on the topic, with the sender its connections' inputs go through
+    fn start_node(
+        redis_url: &str,
+        topic: &str,
+    ) -> (Arc<Holocaster>, UnboundedSender<RequestPacket>) {
+        let holocaster = Arc::new(Holocaster::new(HolocasterConfig {
+            cluster: Some(ClusterConfig {
+                redis_url: String::from(redis_url),
+                topic: String::from(topic),
+                // mini-redis has no Lua scripting
+                shared_seq: false,
+            }),
+            ..Default::default()
+        }));
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let node = holocaster.clone();
+        tokio::spawn(async move { node.run(receiver).await });
+        (holocaster, sender)
+    }
+
+    fn send(sender: &UnboundedSender<RequestPacket>, session_id: Uuid, input: Value) {
+        let input: Input = serde_json::from_value(input).unwrap();
+        sender.send(RequestPacket::new(session_id, input)).unwrap();
+    }
+
+    // The payload of the next output of this type, anything before it is skipped
+    async fn next_output(outbox: &Outbox, output_type: &str) -> Value {
+        let wait = async {
+            loop {
+                if let Some(Outgoing::Packet(packet)) = outbox.recv().await {
+                    let output = serde_json::to_value(&packet.output).unwrap();
+                    if output["type"] == output_type {
+                        return output["payload"].clone();
+                    }
+                }
+            }
+        };
+        time::timeout(Duration::from_secs(5), wait)
+            .await
+            .unwrap_or_else(|_| panic!("no {} output", output_type))
+    }
+
+    #[tokio::test]
+    async fn outputs_reach_sessions_on_other_nodes_through_mini_redis() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let redis_url = format!("redis://{}", listener.local_addr().unwrap());
+        tokio::spawn(mini_redis::server::run(
+            listener,
+            futures::future::pending::<()>(),
+        ));
+
+        let (first, first_sender) = start_node(&redis_url, "holonet-test");
+        let (second, second_sender) = start_node(&redis_url, "holonet-test");
+        // Give both nodes time to subscribe
+        time::sleep(Duration::from_millis(300)).await;
+
+        let (bob_id, bob) = second.connect(&HoloClient::new(), None).await;
+        send(
+            &second_sender,
+            bob_id,
+            json!({"type": "join", "payload": {"userName": "bob"}}),
+        );
+        next_output(&bob, "user-joined").await;
+
+        let (alice_id, alice) = first.connect(&HoloClient::new(), None).await;
+        send(
+            &first_sender,
+            alice_id,
+            json!({"type": "join", "payload": {"userName": "alice"}}),
+        );
+        let joined = next_output(&alice, "user-joined").await;
+        let channel_id = joined["channels"][0]["id"].clone();
+
+        // Alice joining on the first node is announced to bob on the second
+        let announced = next_output(&bob, "user-joined").await;
+        assert_eq!(announced["user"]["name"], "alice");
+
+        send(
+            &first_sender,
+            alice_id,
+            json!({"type": "message", "payload": {"channelId": channel_id, "body": "hi bob"}}),
+        );
+        let message = next_output(&bob, "message").await;
+        assert_eq!(message["message"]["body"], "hi bob");
+        assert_eq!(message["message"]["createdBy"], json!(alice_id));
+    }
+}
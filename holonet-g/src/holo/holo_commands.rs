@@ -0,0 +1,347 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use uuid::Uuid;
+
+use crate::holo::holo_api::{ChannelModelResponse, Output};
+use crate::holo::holo_cluster::Audience;
+use crate::model::session::Session;
+
+// Who ran a command and where, along with everyone connected and every channel they could be in
+pub struct CommandContext<'a> {
+    pub session_id: Uuid,
+    pub user_name: &'a str,
+    // The channel the command was typed in
+    pub channel_id: Uuid,
+    pub registry: &'a CommandRegistry,
+    pub sessions: &'a [Session],
+    // Regular channels only, direct conversations are left out
+    pub channels: &'a [ChannelModelResponse],
+}
+
+impl<'a> CommandContext<'a> {
+    // A user by name, matched the same way names are claimed, see Session::has_name
+    pub fn find_user(&self, name: &str) -> Option<&'a Session> {
+        self.sessions.iter().find(|session| session.has_name(name))
+    }
+
+    // A regular channel by id or by name, Err when there is none or the name is shared
+    pub fn find_channel(&self, name: &str) -> Result<&'a ChannelModelResponse, String> {
+        if let Ok(channel_id) = Uuid::parse_str(name) {
+            if let Some(channel) = self
+                .channels
+                .iter()
+                .find(|channel| channel.id == channel_id)
+            {
+                return Ok(channel);
+            }
+        }
+        let lowercase = name.to_lowercase();
+        let mut matches = self
+            .channels
+            .iter()
+            .filter(|channel| channel.name.to_lowercase() == lowercase);
+        match (matches.next(), matches.next()) {
+            (Some(channel), None) => Ok(channel),
+            (Some(_), Some(_)) => Err(format!(
+                "More than one channel is called {}, join it by id",
+                name
+            )),
+            (None, _) => Err(format!("There is no channel called {}", name)),
+        }
+    }
+}
+
+// What the holocaster does once a command has run, a command can ask for several
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandOutcome {
+    // A system message for the caller only
+    Reply(String),
+    // A system message for everyone in the channel
+    Announce(String),
+    // A system message for one other user, wherever they are
+    Notify { session_id: Uuid, body: String },
+    // Any output, to anyone
+    Send(Audience, Box<Output>),
+    ChangeName(String),
+    JoinChannel(Uuid),
+    LeaveChannel,
+}
+
+// A slash command, game specific commands implement this and are added to the registry
+pub trait Command: Send + Sync {
+    // What follows the slash, matched case-insensitively
+    fn name(&self) -> &str;
+
+    // Arguments as shown by /help, e.g. "<dice>"
+    fn usage(&self) -> &str {
+        ""
+    }
+
+    fn description(&self) -> &str;
+
+    // Err is shown to the caller only, usually as a reminder of how the command is used
+    fn run(&self, context: &CommandContext, args: &str) -> Result<Vec<CommandOutcome>, String>;
+}
+
+// Commands by name, the default registry holds the built-ins
+#[derive(Clone)]
+pub struct CommandRegistry {
+    commands: BTreeMap<String, Arc<dyn Command>>,
+}
+
+impl CommandRegistry {
+    // No commands at all, not even the built-ins
+    pub fn empty() -> Self {
+        CommandRegistry {
+            commands: BTreeMap::new(),
+        }
+    }
+
+    // Adds the command, replacing any command of the same name
+    pub fn register(&mut self, command: impl Command + 'static) {
+        self.commands
+            .insert(command.name().to_lowercase(), Arc::new(command));
+    }
+
+    pub fn with_command(mut self, command: impl Command + 'static) -> Self {
+        self.register(command);
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Command> {
+        self.commands
+            .get(&name.to_lowercase())
+            .map(|command| command.as_ref())
+    }
+
+    // Every command, sorted by name
+    pub fn commands(&self) -> impl Iterator<Item = &dyn Command> {
+        self.commands.values().map(|command| command.as_ref())
+    }
+
+    // Run "/name args", Err when there is no such command
+    pub fn run(
+        &self,
+        context: &CommandContext,
+        input: &str,
+    ) -> Result<Vec<CommandOutcome>, String> {
+        let input = input.trim().trim_start_matches('/');
+        let (name, args) = match input.find(char::is_whitespace) {
+            Some(split) => (&input[..split], input[split..].trim()),
+            None => (input, ""),
+        };
+        match self.get(name) {
+            Some(command) => command.run(context, args),
+            None => Err(format!("Unknown command /{}, try /help", name)),
+        }
+    }
+
+    fn help(&self) -> String {
+        let mut help = String::from("Commands:");
+        for command in self.commands() {
+            let usage = match command.usage() {
+                "" => format!("/{}", command.name()),
+                usage => format!("/{} {}", command.name(), usage),
+            };
+            help.push_str(&format!("\n{} - {}", usage, command.description()));
+        }
+        help
+    }
+}
+
+impl Default for CommandRegistry {
+    fn default() -> Self {
+        CommandRegistry::empty()
+            .with_command(Help)
+            .with_command(Invite)
+            .with_command(Join)
+            .with_command(Leave)
+            .with_command(Me)
+            .with_command(Nick)
+            .with_command(Who)
+    }
+}
+
+// Message bodies starting with a slash and a letter are commands, "/" on its own or "/ hi" is posted as is
+pub fn is_command(body: &str) -> bool {
+    body.trim_start()
+        .strip_prefix('/')
+        .and_then(|rest| rest.chars().next())
+        .is_some_and(char::is_alphanumeric)
+}
+
+struct Help;
+
+impl Command for Help {
+    fn name(&self) -> &str {
+        "help"
+    }
+
+    fn description(&self) -> &str {
+        "List the available commands"
+    }
+
+    fn run(&self, context: &CommandContext, _args: &str) -> Result<Vec<CommandOutcome>, String> {
+        Ok(vec![CommandOutcome::Reply(context.registry.help())])
+    }
+}
+
+struct Invite;
+
+impl Command for Invite {
+    fn name(&self) -> &str {
+        "invite"
+    }
+
+    fn usage(&self) -> &str {
+        "<name>"
+    }
+
+    fn description(&self) -> &str {
+        "Invite someone to this channel"
+    }
+
+    fn run(&self, context: &CommandContext, args: &str) -> Result<Vec<CommandOutcome>, String> {
+        if args.is_empty() {
+            return Err(String::from("Usage: /invite <name>"));
+        }
+        let channel = context
+            .channels
+            .iter()
+            .find(|channel| channel.id == context.channel_id)
+            .ok_or_else(|| String::from("Only regular channels take invites"))?;
+        let invitee = context
+            .find_user(args)
+            .ok_or_else(|| format!("There is no one called {}", args))?;
+        if invitee.is_member(channel.id) {
+            return Err(format!("{} is already here", invitee.name));
+        }
+        Ok(vec![
+            CommandOutcome::Notify {
+                session_id: invitee.id,
+                body: format!(
+                    "{} invited you to #{}, type /join {} to join",
+                    context.user_name, channel.name, channel.id
+                ),
+            },
+            CommandOutcome::Reply(format!("Invited {}", invitee.name)),
+        ])
+    }
+}
+
+struct Join;
+
+impl Command for Join {
+    fn name(&self) -> &str {
+        "join"
+    }
+
+    fn usage(&self) -> &str {
+        "<channel>"
+    }
+
+    fn description(&self) -> &str {
+        "Join a channel by name"
+    }
+
+    fn run(&self, context: &CommandContext, args: &str) -> Result<Vec<CommandOutcome>, String> {
+        let channel = args.trim_start_matches('#');
+        if channel.is_empty() {
+            return Err(String::from("Usage: /join <channel>"));
+        }
+        let channel = context.find_channel(channel)?;
+        Ok(vec![CommandOutcome::JoinChannel(channel.id)])
+    }
+}
+
+struct Leave;
+
+impl Command for Leave {
+    fn name(&self) -> &str {
+        "leave"
+    }
+
+    fn description(&self) -> &str {
+        "Leave this channel"
+    }
+
+    fn run(&self, _context: &CommandContext, _args: &str) -> Result<Vec<CommandOutcome>, String> {
+        Ok(vec![CommandOutcome::LeaveChannel])
+    }
+}
+
+struct Me;
+
+impl Command for Me {
+    fn name(&self) -> &str {
+        "me"
+    }
+
+    fn usage(&self) -> &str {
+        "<action>"
+    }
+
+    fn description(&self) -> &str {
+        "Tell the channel what you are doing"
+    }
+
+    fn run(&self, context: &CommandContext, args: &str) -> Result<Vec<CommandOutcome>, String> {
+        if args.is_empty() {
+            return Err(String::from("Usage: /me <action>"));
+        }
+        Ok(vec![CommandOutcome::Announce(format!(
+            "* {} {}",
+            context.user_name, args
+        ))])
+    }
+}
+
+struct Nick;
+
+impl Command for Nick {
+    fn name(&self) -> &str {
+        "nick"
+    }
+
+    fn usage(&self) -> &str {
+        "<name>"
+    }
+
+    fn description(&self) -> &str {
+        "Change your name"
+    }
+
+    fn run(&self, _context: &CommandContext, args: &str) -> Result<Vec<CommandOutcome>, String> {
+        if args.is_empty() {
+            return Err(String::from("Usage: /nick <name>"));
+        }
+        Ok(vec![CommandOutcome::ChangeName(String::from(args))])
+    }
+}
+
+struct Who;
+
+impl Command for Who {
+    fn name(&self) -> &str {
+        "who"
+    }
+
+    fn description(&self) -> &str {
+        "List who is in this channel"
+    }
+
+    fn run(&self, context: &CommandContext, _args: &str) -> Result<Vec<CommandOutcome>, String> {
+        let mut names: Vec<&str> = context
+            .sessions
+            .iter()
+            .filter(|session| session.is_member(context.channel_id))
+            .map(|session| session.name.as_str())
+            .collect();
+        names.sort_unstable();
+        Ok(vec![CommandOutcome::Reply(format!(
+            "In this channel: {}",
+            names.join(", ")
+        ))])
+    }
+}
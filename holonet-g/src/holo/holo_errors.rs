@@ -5,6 +5,12 @@ pub enum Error {
     System(String),
     Io(io::Error),
     Message(serde_json::Error),
+    // A frame from the client that isn't a valid Input, the connection survives these up to a limit
+    InvalidInput(String),
+    // A frame or message bigger than the configured limit, it was refused before being read in full
+    FrameTooLarge,
+    Cluster(redis::RedisError),
+    Auth(jsonwebtoken::errors::Error),
 }
 
 impl fmt::Display for Error {
@@ -13,6 +19,10 @@ impl fmt::Display for Error {
             Error::System(err) => write!(f, "system error: {}", err),
             Error::Io(ref err) => write!(f, "IO error: {}", err),
             Error::Message(ref err) => write!(f, "Invalid message: {}", err),
+            Error::InvalidInput(err) => write!(f, "Invalid input: {}", err),
+            Error::FrameTooLarge => write!(f, "Frame too large"),
+            Error::Cluster(ref err) => write!(f, "Cluster error: {}", err),
+            Error::Auth(ref err) => write!(f, "Authentication error: {}", err),
         }
     }
 }
@@ -33,5 +43,19 @@ impl From<serde_json::Error> for Error {
     }
 }
 
+// Redis Error Implementation
+impl From<redis::RedisError> for Error {
+    fn from(err: redis::RedisError) -> Self {
+        Error::Cluster(err)
+    }
+}
+
+// Session token Error Implementation
+impl From<jsonwebtoken::errors::Error> for Error {
+    fn from(err: jsonwebtoken::errors::Error) -> Self {
+        Error::Auth(err)
+    }
+}
+
 // provide a result type that throws our custom error trait!
 pub type Result<T> = result::Result<T, Error>;
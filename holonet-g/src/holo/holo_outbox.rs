@@ -0,0 +1,148 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tokio::sync::Notify;
+
+use crate::holo::holo_api::ResponsePacket;
+
+// Close codes sent to the client when the server ends the connection
+pub const CLOSE_GOING_AWAY: u16 = 1001;
+pub const CLOSE_POLICY_VIOLATION: u16 = 1008;
+pub const CLOSE_MESSAGE_TOO_BIG: u16 = 1009;
+pub const CLOSE_TRY_AGAIN_LATER: u16 = 1013;
+
+// What to do with a connection whose outbox is full because the client isn't reading fast enough
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SlowConsumerPolicy {
+    // Make room by dropping the oldest queued packet, the client misses it
+    #[default]
+    DropOldest,
+    // Close the connection, the client is expected to reconnect
+    Disconnect,
+}
+
+// Everything a connection's writer can be asked to put on the socket
+#[derive(Debug, Clone)]
+pub enum Outgoing {
+    Packet(Box<ResponsePacket>),
+    // Protocol level ping, the client answers with a pong
+    Ping,
+    // Always the last item of a closed outbox
+    Close(u16, &'static str),
+}
+
+struct OutboxState {
+    queue: VecDeque<Outgoing>,
+    closed: bool,
+    last_seen: Instant,
+}
+
+// A bounded queue of packets waiting to be written to a single connection
+// The Holocaster pushes into it and the connection's writer drains it, the reader marks it whenever the client shows signs of life
+pub struct Outbox {
+    capacity: usize,
+    policy: SlowConsumerPolicy,
+    state: Mutex<OutboxState>,
+    notify: Notify,
+}
+
+impl Outbox {
+    pub fn new(capacity: usize, policy: SlowConsumerPolicy) -> Self {
+        Outbox {
+            capacity: capacity.max(1),
+            policy,
+            state: Mutex::new(OutboxState {
+                queue: VecDeque::with_capacity(capacity.max(1)),
+                closed: false,
+                last_seen: Instant::now(),
+            }),
+            notify: Notify::new(),
+        }
+    }
+
+    // Queue a packet without waiting, a full outbox is handled by the slow consumer policy
+    pub fn push(&self, packet: ResponsePacket) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.closed {
+                return;
+            }
+            if state.queue.len() >= self.capacity {
+                match self.policy {
+                    SlowConsumerPolicy::DropOldest => {
+                        state.queue.pop_front();
+                    }
+                    SlowConsumerPolicy::Disconnect => {
+                        println!(
+                            "Outbox for session {} is full, disconnecting slow consumer",
+                            packet.session_id
+                        );
+                        state.queue.clear();
+                        drop(state);
+                        self.close(CLOSE_TRY_AGAIN_LATER, "try again later");
+                        return;
+                    }
+                }
+            }
+            state.queue.push_back(Outgoing::Packet(Box::new(packet)));
+        }
+        self.notify.notify_one();
+    }
+
+    // Pings skip the capacity check, they are tiny and a full outbox is exactly when we want one through
+    pub fn ping(&self) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.closed {
+                return;
+            }
+            state.queue.push_back(Outgoing::Ping);
+        }
+        self.notify.notify_one();
+    }
+
+    // Wait for the next item, None once the outbox has been closed and drained
+    pub async fn recv(&self) -> Option<Outgoing> {
+        loop {
+            if let Some(item) = self.try_recv() {
+                return Some(item);
+            }
+            if self.state.lock().unwrap().closed {
+                return None;
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    // Take the next item only if one is already queued
+    pub fn try_recv(&self) -> Option<Outgoing> {
+        self.state.lock().unwrap().queue.pop_front()
+    }
+
+    // Have the writer close the socket once it has flushed what is already queued
+    pub fn close(&self, code: u16, reason: &'static str) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.closed {
+                return;
+            }
+            state.queue.push_back(Outgoing::Close(code, reason));
+            state.closed = true;
+        }
+        self.notify.notify_one();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+
+    // Any frame from the client counts, pongs included
+    pub fn touch(&self) {
+        self.state.lock().unwrap().last_seen = Instant::now();
+    }
+
+    pub fn idle_for(&self) -> Duration {
+        self.state.lock().unwrap().last_seen.elapsed()
+    }
+}
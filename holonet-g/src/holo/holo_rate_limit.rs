@@ -0,0 +1,440 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+// Buckets that have refilled completely are forgotten once this many addresses are tracked
+const MAX_TRACKED_ADDRESSES: usize = 4096;
+
+// Up to `burst` actions at once, earning them back at a steady `burst` per `per`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per: Duration,
+}
+
+impl RateLimit {
+    pub fn per_second(burst: u32) -> Self {
+        RateLimit {
+            burst,
+            per: Duration::from_secs(1),
+        }
+    }
+
+    pub fn per_minute(burst: u32) -> Self {
+        RateLimit {
+            burst,
+            per: Duration::from_secs(60),
+        }
+    }
+}
+
+// Every limit is off when unset
+#[derive(Debug, Clone, Default)]
+pub struct RateLimitConfig {
+    // New connections from one remote address
+    pub connections: Option<RateLimit>,
+    // Messages posted or edited by one session
+    pub messages: Option<RateLimit>,
+    // Channels joined by one session, joining the server counts as joining the holonet channel
+    pub joins: Option<RateLimit>,
+    // Messages from every session on one remote address, so opening more sessions doesn't earn more
+    pub address_messages: Option<RateLimit>,
+    // Channels joined by every session on one remote address, the server included
+    pub address_joins: Option<RateLimit>,
+    // Name and status changes by one session, each one is broadcast to everyone online
+    pub profile_updates: Option<RateLimit>,
+    // Channels created by one session
+    pub channel_creates: Option<RateLimit>,
+    // Rate limited requests a session may rack up before it is disconnected, earned back like any other limit
+    pub max_violations: Option<RateLimit>,
+}
+
+// What a session is trying to do, for picking the bucket it is charged to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Message,
+    Join,
+    ProfileUpdate,
+    CreateChannel,
+}
+
+// What happened to a session's rate limited request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    Allowed,
+    // Refused, the client may try again after this long
+    Limited(Duration),
+    // Refused, and the session has been limited too often to keep its connection
+    Disconnect(Duration),
+}
+
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit) -> Self {
+        TokenBucket {
+            limit,
+            tokens: f64::from(limit.burst),
+            refilled_at: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let earned = now.duration_since(self.refilled_at).as_secs_f64()
+            / self.limit.per.as_secs_f64()
+            * f64::from(self.limit.burst);
+        self.tokens = (self.tokens + earned).min(f64::from(self.limit.burst));
+        self.refilled_at = now;
+    }
+
+    // Spend a token, or say how long until the next one is earned
+    fn take(&mut self) -> Result<(), Duration> {
+        self.refill();
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+        let missing = 1.0 - self.tokens;
+        Err(self
+            .limit
+            .per
+            .mul_f64(missing / f64::from(self.limit.burst)))
+    }
+
+    // Return a token spent on an action that was refused after all
+    fn give_back(&mut self) {
+        self.tokens = (self.tokens + 1.0).min(f64::from(self.limit.burst));
+    }
+
+    fn is_full(&mut self) -> bool {
+        self.refill();
+        self.tokens >= f64::from(self.limit.burst)
+    }
+}
+
+#[derive(Default)]
+struct SessionBuckets {
+    messages: Option<TokenBucket>,
+    joins: Option<TokenBucket>,
+    profile_updates: Option<TokenBucket>,
+    channel_creates: Option<TokenBucket>,
+    violations: Option<TokenBucket>,
+    // Where the session is connected from, its messages and joins are charged there too
+    address: Option<IpAddr>,
+}
+
+#[derive(Default)]
+struct AddressBuckets {
+    messages: Option<TokenBucket>,
+    joins: Option<TokenBucket>,
+}
+
+impl AddressBuckets {
+    fn is_full(&mut self) -> bool {
+        self.messages.as_mut().is_none_or(TokenBucket::is_full)
+            && self.joins.as_mut().is_none_or(TokenBucket::is_full)
+    }
+}
+
+// Token buckets per remote address and per session, a limit of zero is treated as unset
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    connections: Mutex<HashMap<IpAddr, TokenBucket>>,
+    // Kept when a session ends, a new session from the same address picks up where it left off
+    addresses: Mutex<HashMap<IpAddr, AddressBuckets>>,
+    sessions: Mutex<HashMap<Uuid, SessionBuckets>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        let enabled = |limit: Option<RateLimit>| {
+            limit.filter(|limit| limit.burst > 0 && !limit.per.is_zero())
+        };
+        RateLimiter {
+            config: RateLimitConfig {
+                connections: enabled(config.connections),
+                messages: enabled(config.messages),
+                joins: enabled(config.joins),
+                address_messages: enabled(config.address_messages),
+                address_joins: enabled(config.address_joins),
+                profile_updates: enabled(config.profile_updates),
+                channel_creates: enabled(config.channel_creates),
+                max_violations: enabled(config.max_violations),
+            },
+            connections: Default::default(),
+            addresses: Default::default(),
+            sessions: Default::default(),
+        }
+    }
+
+    // Charge a new connection to its remote address
+    pub fn admit_connection(&self, address: IpAddr) -> Result<(), Duration> {
+        let limit = match self.config.connections {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        let mut connections = self.connections.lock().unwrap();
+        if connections.len() >= MAX_TRACKED_ADDRESSES {
+            connections.retain(|_, bucket| !bucket.is_full());
+        }
+        connections
+            .entry(address)
+            .or_insert_with(|| TokenBucket::new(limit))
+            .take()
+    }
+
+    // Remember where the session is connected from, None leaves it to its own buckets
+    pub fn bind_address(&self, session_id: Uuid, address: Option<IpAddr>) {
+        self.sessions
+            .lock()
+            .unwrap()
+            .entry(session_id)
+            .or_default()
+            .address = address;
+    }
+
+    // Charge an action to the session and its address, repeated refusals count against the violation limit
+    pub fn check(&self, session_id: Uuid, action: Action) -> Verdict {
+        let limit = match action {
+            Action::Message => self.config.messages,
+            Action::Join => self.config.joins,
+            Action::ProfileUpdate => self.config.profile_updates,
+            Action::CreateChannel => self.config.channel_creates,
+        };
+
+        let mut sessions = self.sessions.lock().unwrap();
+        let buckets = sessions.entry(session_id).or_default();
+        let bucket = match action {
+            Action::Message => &mut buckets.messages,
+            Action::Join => &mut buckets.joins,
+            Action::ProfileUpdate => &mut buckets.profile_updates,
+            Action::CreateChannel => &mut buckets.channel_creates,
+        };
+        let mut bucket = limit.map(|limit| bucket.get_or_insert_with(|| TokenBucket::new(limit)));
+        let charged = match bucket.as_mut().map(|bucket| bucket.take()) {
+            Some(Err(retry_after)) => Err(retry_after),
+            // The session's token is handed back when its address has none left
+            _ => self
+                .charge_address(buckets.address, action)
+                .inspect_err(|_| {
+                    if let Some(bucket) = bucket {
+                        bucket.give_back();
+                    }
+                }),
+        };
+        let retry_after = match charged {
+            Ok(()) => return Verdict::Allowed,
+            Err(retry_after) => retry_after,
+        };
+
+        let max_violations = match self.config.max_violations {
+            Some(max_violations) => max_violations,
+            None => return Verdict::Limited(retry_after),
+        };
+        let violations = buckets
+            .violations
+            .get_or_insert_with(|| TokenBucket::new(max_violations));
+        match violations.take() {
+            Ok(()) => Verdict::Limited(retry_after),
+            Err(_) => Verdict::Disconnect(retry_after),
+        }
+    }
+
+    fn charge_address(&self, address: Option<IpAddr>, action: Action) -> Result<(), Duration> {
+        let limit = match action {
+            Action::Message => self.config.address_messages,
+            Action::Join => self.config.address_joins,
+            Action::ProfileUpdate | Action::CreateChannel => None,
+        };
+        let (address, limit) = match (address, limit) {
+            (Some(address), Some(limit)) => (address, limit),
+            _ => return Ok(()),
+        };
+        let mut addresses = self.addresses.lock().unwrap();
+        if addresses.len() >= MAX_TRACKED_ADDRESSES {
+            addresses.retain(|_, buckets| !buckets.is_full());
+        }
+        let buckets = addresses.entry(address).or_default();
+        let bucket = match action {
+            Action::Join => &mut buckets.joins,
+            _ => &mut buckets.messages,
+        };
+        bucket.get_or_insert_with(|| TokenBucket::new(limit)).take()
+    }
+
+    // The session's own buckets go, its address keeps what it has spent
+    pub fn forget_session(&self, session_id: Uuid) {
+        self.sessions.lock().unwrap().remove(&session_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_allows_a_burst_then_waits() {
+        let mut bucket = TokenBucket::new(RateLimit::per_minute(2));
+        assert!(bucket.take().is_ok());
+        assert!(bucket.take().is_ok());
+
+        // One token is earned every 30 seconds
+        let retry_after = bucket.take().unwrap_err();
+        assert!(retry_after > Duration::from_secs(29) && retry_after <= Duration::from_secs(30));
+    }
+
+    #[test]
+    fn bucket_refills_over_time_up_to_its_burst() {
+        let mut bucket = TokenBucket::new(RateLimit::per_minute(2));
+        bucket.take().unwrap();
+        bucket.take().unwrap();
+        assert!(!bucket.is_full());
+
+        bucket.refilled_at -= Duration::from_secs(30);
+        assert!(bucket.take().is_ok());
+        assert!(bucket.take().is_err());
+
+        bucket.refilled_at -= Duration::from_secs(600);
+        assert!(bucket.is_full());
+        assert!(bucket.take().is_ok());
+        assert!(bucket.take().is_ok());
+        assert!(bucket.take().is_err());
+    }
+
+    #[test]
+    fn unset_and_zero_limits_allow_everything() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            messages: Some(RateLimit::per_second(0)),
+            joins: Some(RateLimit {
+                burst: 5,
+                per: Duration::ZERO,
+            }),
+            ..Default::default()
+        });
+        let session_id = Uuid::new_v4();
+        for _ in 0..100 {
+            assert_eq!(limiter.check(session_id, Action::Message), Verdict::Allowed);
+            assert_eq!(limiter.check(session_id, Action::Join), Verdict::Allowed);
+            assert_eq!(
+                limiter.check(session_id, Action::ProfileUpdate),
+                Verdict::Allowed
+            );
+        }
+        assert!(limiter.admit_connection([127, 0, 0, 1].into()).is_ok());
+    }
+
+    #[test]
+    fn actions_and_sessions_have_their_own_buckets() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            messages: Some(RateLimit::per_minute(1)),
+            joins: Some(RateLimit::per_minute(1)),
+            profile_updates: Some(RateLimit::per_minute(1)),
+            channel_creates: Some(RateLimit::per_minute(1)),
+            ..Default::default()
+        });
+        let session_id = Uuid::new_v4();
+        for action in &[
+            Action::Message,
+            Action::Join,
+            Action::ProfileUpdate,
+            Action::CreateChannel,
+        ] {
+            assert_eq!(limiter.check(session_id, *action), Verdict::Allowed);
+            assert!(matches!(
+                limiter.check(session_id, *action),
+                Verdict::Limited(_)
+            ));
+        }
+
+        assert_eq!(
+            limiter.check(Uuid::new_v4(), Action::Message),
+            Verdict::Allowed
+        );
+
+        // A session that comes back starts with full buckets
+        limiter.forget_session(session_id);
+        assert_eq!(limiter.check(session_id, Action::Message), Verdict::Allowed);
+    }
+
+    #[test]
+    fn repeat_offenders_are_disconnected() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            messages: Some(RateLimit::per_minute(1)),
+            max_violations: Some(RateLimit::per_minute(2)),
+            ..Default::default()
+        });
+        let session_id = Uuid::new_v4();
+        assert_eq!(limiter.check(session_id, Action::Message), Verdict::Allowed);
+        assert!(matches!(
+            limiter.check(session_id, Action::Message),
+            Verdict::Limited(_)
+        ));
+        assert!(matches!(
+            limiter.check(session_id, Action::Message),
+            Verdict::Limited(_)
+        ));
+        assert!(matches!(
+            limiter.check(session_id, Action::Message),
+            Verdict::Disconnect(_)
+        ));
+    }
+
+    #[test]
+    fn sessions_on_one_address_share_its_buckets() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            messages: Some(RateLimit::per_minute(2)),
+            address_messages: Some(RateLimit::per_minute(3)),
+            address_joins: Some(RateLimit::per_minute(1)),
+            ..Default::default()
+        });
+        let address = [10, 0, 0, 1].into();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        limiter.bind_address(first, Some(address));
+        limiter.bind_address(second, Some(address));
+
+        assert_eq!(limiter.check(first, Action::Message), Verdict::Allowed);
+        assert_eq!(limiter.check(first, Action::Message), Verdict::Allowed);
+        assert_eq!(limiter.check(second, Action::Message), Verdict::Allowed);
+        assert!(matches!(
+            limiter.check(second, Action::Message),
+            Verdict::Limited(_)
+        ));
+
+        // Joins only have an address limit here, and a session's address outlives it
+        assert_eq!(limiter.check(first, Action::Join), Verdict::Allowed);
+        limiter.forget_session(first);
+        let third = Uuid::new_v4();
+        limiter.bind_address(third, Some(address));
+        assert!(matches!(
+            limiter.check(third, Action::Join),
+            Verdict::Limited(_)
+        ));
+
+        // A session whose address has nothing left keeps its own tokens for later
+        let elsewhere = Uuid::new_v4();
+        limiter.bind_address(elsewhere, Some([10, 0, 0, 2].into()));
+        assert_eq!(limiter.check(elsewhere, Action::Message), Verdict::Allowed);
+        limiter.bind_address(second, Some([10, 0, 0, 3].into()));
+        assert_eq!(limiter.check(second, Action::Message), Verdict::Allowed);
+    }
+
+    #[test]
+    fn connections_are_limited_per_address() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            connections: Some(RateLimit::per_minute(1)),
+            ..Default::default()
+        });
+        assert!(limiter.admit_connection([10, 0, 0, 1].into()).is_ok());
+        assert!(limiter.admit_connection([10, 0, 0, 1].into()).is_err());
+        assert!(limiter.admit_connection([10, 0, 0, 2].into()).is_ok());
+    }
+}
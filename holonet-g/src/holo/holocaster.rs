@@ -1,38 +1,150 @@
 use chrono::prelude::*;
-use std::collections::HashMap;
-use std::time::Duration;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 // use chrono::Utc;
-// use regex::Regex;
 // use futures::{StreamExt, TryStream, TryStreamExt};
 use tokio::sync::mpsc::UnboundedReceiver;
-use tokio::sync::{broadcast, RwLock};
+use tokio::sync::RwLock;
 use tokio::time;
 // use tokio_stream::wrappers;
+use regex::Regex;
+use unicode_segmentation::UnicodeSegmentation;
 use uuid::Uuid;
 
 use crate::holo::holo_api::{
-    ChannelModelResponse, ErrorOutput, Input, JoinEvent, MessageEvent, MessageModelResponse,
-    Output, RequestPacket, ResponsePacket, UserDiscconnectOutput, UserJoinedOutput,
-    UserMessageOutput, UserModelResponse,
+    ChangeNameEvent, ChannelCreatedOutput, ChannelJoinedOutput, ChannelLeftOutput,
+    ChannelListOutput, ChannelModelResponse, ChannelRosterResponse, ChannelWaitlistedOutput,
+    CreateChannelEvent, DeleteMessageEvent, DirectMessageEvent, DirectMessageOutput,
+    EditMessageEvent, ErrorOutput, FetchHistoryEvent, FetchThreadEvent, FetchUnreadEvent,
+    HistoryOutput, Input, JoinChannelEvent, JoinEvent, LeaveChannelEvent, ListChannelsEvent,
+    ListUsersEvent, MarkReadEvent, MessageDeletedOutput, MessageEvent, MessageModelResponse,
+    Output, ReactionEvent, ReactionUpdatedOutput, ReadReceiptOutput, RequestPacket, ResponsePacket,
+    SessionResumedOutput, SetStatusEvent, StatusChangedOutput, SystemMessageOutput, ThreadOutput,
+    ThreadUpdatedOutput, TypingEvent, TypingOutput, UnreadCountsOutput, UserDiscconnectOutput,
+    UserJoinedOutput, UserListOutput, UserMessageOutput, UserModelResponse, UserRenamedOutput,
 };
-// use crate::holo::holo_errors::{HoloError, Result};
-use crate::model::channel::Channel;
+use crate::holo::holo_client::HoloClient;
+use crate::holo::holo_cluster::{
+    Audience, ClusterConfig, ClusterMessage, HoloCluster, ReplicatedStore,
+};
+use crate::holo::holo_commands::{self, CommandContext, CommandOutcome, CommandRegistry};
+use crate::holo::holo_errors;
+use crate::holo::holo_outbox::{
+    Outbox, Outgoing, SlowConsumerPolicy, CLOSE_GOING_AWAY, CLOSE_POLICY_VIOLATION,
+};
+use crate::holo::holo_rate_limit::{Action, RateLimitConfig, RateLimiter, Verdict};
+use crate::model::channel::{self, Channel, MessageCursor};
 use crate::model::message::Message;
-use crate::model::session::Session;
+use crate::model::session::{is_valid_user_name, Session};
+use crate::store::{self, HoloStore, StorageConfig};
 
-const MAX_MESSAGE_BODY_LENGTH: usize = 256;
+const MAX_CHANNEL_NAME_LENGTH: usize = 64;
+// A reaction is a single emoji, and a message holds a limited number of different ones
+const MAX_REACTION_LENGTH: usize = 32;
+const MAX_REACTIONS_PER_MESSAGE: usize = 20;
+const DEFAULT_HISTORY_PAGE_SIZE: usize = 50;
+// Message bodies are limited both in bytes and in user visible characters, so emoji heavy messages aren't cut short
+const DEFAULT_MAX_MESSAGE_BYTES: usize = 4096;
+const DEFAULT_MAX_MESSAGE_GRAPHEMES: usize = 256;
+const DEFAULT_OUTBOX_CAPACITY: usize = 256;
+const DEFAULT_MAX_MISSED_PONGS: u32 = 3;
+const RESUME_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+// How many message nonces are remembered per session for deduping retries
+const MAX_NONCES_PER_SESSION: usize = 64;
+// A typing indicator stops on its own unless typing-start is repeated within the timeout
+const TYPING_TIMEOUT: Duration = Duration::from_secs(5);
+// At most one typing-started is relayed per session and channel in this window
+const TYPING_THROTTLE: Duration = Duration::from_secs(1);
+const TYPING_SWEEP_INTERVAL: Duration = Duration::from_millis(500);
 
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Default)]
 pub struct HolocasterConfig {
+    // How often every connection is pinged, connections are never pinged or evicted when unset
     pub alive_interval: Option<Duration>,
+    // Connections that stay silent for this many pings in a row are evicted
+    pub max_missed_pongs: Option<u32>,
+    pub storage: StorageConfig,
+    // When set, outputs are fanned out to every other node listening on the same Redis topic
+    pub cluster: Option<ClusterConfig>,
+    // Member limit for new channels that don't set their own, the default holonet channel is never capped
+    // Members are counted per node, so in cluster mode the limit applies to each node separately
+    pub max_channel_members: Option<usize>,
+    // How many packets may queue up for a connection before the slow consumer policy kicks in
+    pub outbox_capacity: Option<usize>,
+    pub slow_consumer_policy: SlowConsumerPolicy,
+    // How long a dropped session is held for its client to resume it, sessions end with the connection when unset
+    pub resume_grace: Option<Duration>,
+    // Longest message body accepted, in UTF-8 bytes
+    pub max_message_bytes: Option<usize>,
+    // Longest message body accepted, in grapheme clusters, a family emoji counts once
+    pub max_message_graphemes: Option<usize>,
+    // Limits on connections per remote address and on messages and joins per session
+    pub rate_limits: RateLimitConfig,
+    // Relay every advanced read cursor to the other members of the channel as a read-receipt
+    pub broadcast_read_receipts: bool,
+    // Slash commands messages can run instead of being posted, the default holds the built-ins
+    pub commands: CommandRegistry,
 }
 
 pub struct Holocaster {
     alive_interval: Option<Duration>,
-    response_sender: broadcast::Sender<ResponsePacket>,
-    sessions: RwLock<HashMap<Uuid, Session>>,
-    channels: RwLock<Vec<Channel>>,
+    max_missed_pongs: u32,
+    // Every open connection has its own outbox, keyed by session id
+    outboxes: RwLock<HashMap<Uuid, Arc<Outbox>>>,
+    outbox_capacity: usize,
+    slow_consumer_policy: SlowConsumerPolicy,
+    resume_grace: Option<Duration>,
+    max_message_bytes: usize,
+    max_message_graphemes: usize,
+    rate_limiter: RateLimiter,
+    broadcast_read_receipts: bool,
+    // Sessions whose connection dropped, their outbox keeps collecting outputs until they resume or the grace window ends
+    detached: RwLock<HashMap<Uuid, Instant>>,
+    // Outstanding resume tokens and the session each one resumes
+    resume_tokens: RwLock<HashMap<String, Uuid>>,
+    // Recent message nonces per session and the message each one posted, oldest first
+    nonces: RwLock<HashMap<Uuid, VecDeque<(String, Uuid)>>>,
+    // Live sessions, channels and messages
+    store: RwLock<Box<dyn HoloStore>>,
+    cluster: Option<HoloCluster>,
+    max_channel_members: Option<usize>,
+    // Sessions queued for a seat in a full channel, longest waiting first
+    waitlists: RwLock<HashMap<Uuid, VecDeque<Uuid>>>,
+    // Typing indicators keyed by session and channel, kept in memory only
+    typing: RwLock<HashMap<(Uuid, Uuid), Typing>>,
+    commands: CommandRegistry,
+    // Sessions whose name comes from their auth token, /nick can't change it
+    authenticated: RwLock<HashSet<Uuid>>,
+}
+
+struct Typing {
+    // Set while the session is typing
+    expires_at: Option<Instant>,
+    // When typing-started was last relayed, for throttling
+    relayed_at: Instant,
+}
+
+// Outcome of asking for a seat in a channel
+enum ChannelSeat {
+    Joined,
+    AlreadyMember,
+    Waitlisted(usize),
+    Full,
+}
+
+// The request being handled, so replies to its sender can carry its requestId
+struct RequestContext {
+    session_id: Uuid,
+    request_id: Option<String>,
+    replied: Cell<bool>,
+}
+
+tokio::task_local! {
+    static CURRENT_REQUEST: RequestContext;
 }
 
 pub struct DefaultHolonetChannel {
@@ -45,33 +157,70 @@ lazy_static! {
         name: String::from("holonet"),
         id: Uuid::parse_str("65fe9132-a31f-11eb-bcbc-0242ac130002").unwrap()
     };
+    // An @ that doesn't follow a word character, so email addresses aren't mentions
+    static ref MENTION_PATTERN: Regex = Regex::new(r"(?:^|[^\w@])@(\w+(?:[.\-]\w+)*)").unwrap();
 }
 
 // This is intended to be stored in an Arc and be leveraged concurrently across all active processes
 impl Holocaster {
     pub fn new(config: HolocasterConfig) -> Self {
-        // TODO: i think this means we can maintain 16 channels sending messages at once?
-        // 16 is what i found in default documents...
-        let (response_sender, _) = broadcast::channel(16);
+        let mut store = store::open(&config.storage)
+            .expect("Failed to open the Holonet store. View HolocasterConfig.storage.");
 
         // Seed the Holonet Default channel
-        // TODO: (for now all users will only use this one chat-channel)
-        let channel_default: Vec<Channel> = vec![Channel::new(
-            DEFAULT_HOLONET_CHANNEL.id,
-            &DEFAULT_HOLONET_CHANNEL.name,
-            Uuid::nil(),
-        )];
+        if store.channel_get(DEFAULT_HOLONET_CHANNEL.id).is_none() {
+            store
+                .channel_save(Channel::new(
+                    DEFAULT_HOLONET_CHANNEL.id,
+                    &DEFAULT_HOLONET_CHANNEL.name,
+                    Uuid::nil(),
+                ))
+                .expect("Failed to seed the default Holonet channel.");
+        }
+
+        let cluster = config.cluster.map(|cluster| {
+            HoloCluster::new(cluster)
+                .expect("Failed to configure the Holonet cluster. View HolocasterConfig.cluster.")
+        });
+        // Every node keeps its own copy of the history, each write is replayed on the others
+        let store: Box<dyn HoloStore> = match &cluster {
+            Some(cluster) => Box::new(ReplicatedStore::new(store, cluster.publisher())),
+            None => store,
+        };
 
         Holocaster {
             alive_interval: config.alive_interval,
-            response_sender: response_sender,
-            sessions: Default::default(),
-            channels: RwLock::new(channel_default),
+            max_missed_pongs: config
+                .max_missed_pongs
+                .unwrap_or(DEFAULT_MAX_MISSED_PONGS)
+                .max(1),
+            outboxes: Default::default(),
+            outbox_capacity: config.outbox_capacity.unwrap_or(DEFAULT_OUTBOX_CAPACITY),
+            slow_consumer_policy: config.slow_consumer_policy,
+            resume_grace: config.resume_grace,
+            max_message_bytes: config
+                .max_message_bytes
+                .unwrap_or(DEFAULT_MAX_MESSAGE_BYTES),
+            max_message_graphemes: config
+                .max_message_graphemes
+                .unwrap_or(DEFAULT_MAX_MESSAGE_GRAPHEMES),
+            rate_limiter: RateLimiter::new(config.rate_limits),
+            broadcast_read_receipts: config.broadcast_read_receipts,
+            detached: Default::default(),
+            resume_tokens: Default::default(),
+            nonces: Default::default(),
+            store: RwLock::new(store),
+            cluster,
+            max_channel_members: config.max_channel_members,
+            waitlists: Default::default(),
+            typing: Default::default(),
+            commands: config.commands,
+            authenticated: Default::default(),
         }
     }
 
     // This kicks off the party
-    // If we don't get any activity at the end of our alive_interval, then we fire off a Keep alive message, which closes out the channel
+    // Every alive_interval each connection is pinged, and connections that stopped answering are evicted
     pub async fn run(&self, request_stream: UnboundedReceiver<RequestPacket>) {
         let process_keep_alive_ticker = self.process_keep_alive();
 
@@ -81,28 +230,415 @@ impl Holocaster {
             _ = self.handle_incoming(request_stream) => {
                 println!("COMPLETED sending packets!");
             } ,
+            _ = self.process_cluster() => {
+                println!("CLUSTER CONNECTION CLOSED!");
+            },
+            _ = self.process_resume_grace() => {
+                println!("RESUME SWEEP FAILED!");
+            },
+            _ = self.process_typing_expiry() => {
+                println!("TYPING SWEEP FAILED!");
+            },
+        }
+    }
+
+    // Replay outputs published by other nodes to our own sessions, and their writes to our store
+    async fn process_cluster(&self) {
+        match &self.cluster {
+            Some(cluster) => {
+                cluster
+                    .run(|event| self.receive_cluster_message(event.message))
+                    .await
+            }
+            None => futures::future::pending().await,
+        }
+    }
+
+    async fn receive_cluster_message(&self, message: ClusterMessage) {
+        match message {
+            ClusterMessage::Output { audience, output } => self.deliver(audience, output).await,
+            ClusterMessage::StoreChange(change) => {
+                if let Err(err) = self.store.write().await.apply(change) {
+                    println!("Failed to apply a change from another node: {}", err);
+                }
+            }
         }
     }
 
+    // With a shared counter messages are numbered by the cluster, None when they are numbered by this node's store
+    // Err when the counter can't be reached, the message isn't posted rather than risk a seq another node also used
+    async fn cluster_seq(&self, channel_id: Uuid) -> Result<Option<u64>, ErrorOutput> {
+        let cluster = match &self.cluster {
+            Some(cluster) if cluster.shares_seq() => cluster,
+            _ => return Ok(None),
+        };
+        let floor = self.last_seq(channel_id).await + 1;
+        cluster
+            .next_seq(channel_id, floor)
+            .await
+            .map(Some)
+            .map_err(|err| {
+                println!("Failed to number a message in {}: {}", channel_id, err);
+                ErrorOutput::ServerError
+            })
+    }
+
     async fn handle_incoming(&self, mut request_stream: UnboundedReceiver<RequestPacket>) {
         while let Some(i) = request_stream.recv().await {
-            self.handle_message(i).await;
+            let context = RequestContext {
+                session_id: i.session_id,
+                request_id: i.request_id.clone(),
+                replied: Cell::new(false),
+            };
+            CURRENT_REQUEST.scope(context, self.handle_request(i)).await;
+        }
+    }
+
+    async fn handle_request(&self, request_packet: RequestPacket) {
+        let session_id = request_packet.session_id;
+        if self.is_rate_limited(session_id, &request_packet.body).await {
+            return;
+        }
+        self.handle_message(request_packet).await;
+
+        // Inputs with no natural reply are acknowledged so the client isn't left waiting on its requestId
+        let unanswered =
+            CURRENT_REQUEST.with(|request| request.request_id.is_some() && !request.replied.get());
+        if unanswered {
+            self.send_session_id(session_id, Output::Ack).await;
+        }
+    }
+
+    // Register an outbox for a new connection and return the session id it speaks for
+    // A valid resume token reattaches the connection to its detached session, an authenticated client can only resume its own
+    // The newest connection wins, an older one under the same session id is closed
+    pub async fn connect(
+        &self,
+        client: &HoloClient,
+        resume_token: Option<&str>,
+    ) -> (Uuid, Arc<Outbox>) {
+        let mut resume_failed = false;
+        if let Some(resume_token) = resume_token {
+            let expected_id = client.identity.as_ref().map(|identity| identity.user_id);
+            match self.resume(resume_token, expected_id).await {
+                Some(resumed) => {
+                    self.rate_limiter.bind_address(resumed.0, client.remote);
+                    return resumed;
+                }
+                None => resume_failed = true,
+            }
+        }
+
+        // Reconnecting under the same id without resuming ends the old session for good, connected or detached
+        let previous = self.outboxes.read().await.get(&client.id).cloned();
+        if let Some(previous) = previous {
+            previous.close(CLOSE_GOING_AWAY, "connected elsewhere");
+            self.remove_session(client.id).await;
+        }
+
+        let outbox = Arc::new(Outbox::new(self.outbox_capacity, self.slow_consumer_policy));
+        // Two connections racing for the same id, the one that lands last wins here too
+        let raced = self
+            .outboxes
+            .write()
+            .await
+            .insert(client.id, outbox.clone());
+        if let Some(raced) = raced {
+            raced.close(CLOSE_GOING_AWAY, "connected elsewhere");
+        }
+        if client.identity.is_some() {
+            self.authenticated.write().await.insert(client.id);
+        }
+        self.rate_limiter.bind_address(client.id, client.remote);
+
+        // The client is free to start over with a join
+        if resume_failed {
+            self.send_error(client.id, ErrorOutput::ResumeFailed).await;
+        }
+        (client.id, outbox)
+    }
+
+    // Swap the session's outbox for a new one, led by session-resumed and then what it missed
+    // Missed outputs wait in a buffer no bigger than any outbox, older ones are dropped and the client fills the gap with fetch-history
+    // The session may still look connected when its client comes back before we noticed the drop, that connection is closed
+    async fn resume(
+        &self,
+        resume_token: &str,
+        expected_id: Option<Uuid>,
+    ) -> Option<(Uuid, Arc<Outbox>)> {
+        let session_id = *self.resume_tokens.read().await.get(resume_token)?;
+        if expected_id.is_some_and(|expected_id| expected_id != session_id) {
+            return None;
+        }
+        self.resume_tokens.write().await.remove(resume_token);
+
+        // The store lock is let go first, remove_session takes it again
+        let session = self.store.read().await.session_get(session_id).cloned();
+        let session = match session {
+            Some(session) => session,
+            None => {
+                self.remove_session(session_id).await;
+                return None;
+            }
+        };
+
+        let outbox = Arc::new(Outbox::new(self.outbox_capacity, self.slow_consumer_policy));
+        let output = SessionResumedOutput::new(
+            self.get_unread_channels(&session).await,
+            UserModelResponse::from(&session),
+            self.issue_resume_token(session_id).await,
+            self.get_rosters(&session).await,
+        );
+        outbox.push(ResponsePacket::new(
+            session_id,
+            Uuid::nil(),
+            Output::SessionResumed(output),
+        ));
+
+        // Swapped under the outboxes lock so nothing sent in the meantime ends up in the old outbox
+        let previous = {
+            let mut outboxes = self.outboxes.write().await;
+            match outboxes.get(&session_id).cloned() {
+                // A buffer that overflowed under SlowConsumerPolicy::Disconnect can't be replayed
+                Some(previous) if !previous.is_closed() => {
+                    let mut missed = Vec::new();
+                    while let Some(item) = previous.try_recv() {
+                        if let Outgoing::Packet(packet) = item {
+                            missed.push(*packet);
+                        }
+                    }
+                    // Only the newest ones that fit behind session-resumed, so it is never the one dropped
+                    let skipped = missed
+                        .len()
+                        .saturating_sub(self.outbox_capacity.saturating_sub(1));
+                    for packet in missed.into_iter().skip(skipped) {
+                        outbox.push(packet);
+                    }
+                    outboxes.insert(session_id, outbox.clone());
+                    self.detached.write().await.remove(&session_id);
+                    Some(previous)
+                }
+                _ => None,
+            }
+        };
+        let previous = match previous {
+            Some(previous) => previous,
+            None => {
+                self.remove_session(session_id).await;
+                return None;
+            }
+        };
+        // The old connection's disconnect no longer matches the session's outbox, so it changes nothing
+        previous.close(CLOSE_GOING_AWAY, "resumed elsewhere");
+
+        println!("Session {} resumed", session_id);
+        Some((session_id, outbox))
+    }
+
+    async fn issue_resume_token(&self, session_id: Uuid) -> String {
+        let resume_token = Uuid::new_v4().to_simple().to_string();
+        self.resume_tokens
+            .write()
+            .await
+            .insert(resume_token.clone(), session_id);
+        resume_token
+    }
+
+    // Charge the input to its session's limits, refused inputs are answered here
+    async fn is_rate_limited(&self, session_id: Uuid, input: &Input) -> bool {
+        let action = match input {
+            Input::Message(_)
+            | Input::EditMessage(_)
+            | Input::DirectMessage(_)
+            | Input::AddReaction(_)
+            | Input::RemoveReaction(_) => Action::Message,
+            // Joining the server joins the holonet channel, so it is charged like any other join
+            Input::Join(_) | Input::JoinChannel(_) => Action::Join,
+            Input::ChangeName(_) | Input::SetStatus(_) => Action::ProfileUpdate,
+            Input::CreateChannel(_) => Action::CreateChannel,
+            _ => return false,
+        };
+        let (retry_after, disconnect) = match self.rate_limiter.check(session_id, action) {
+            Verdict::Allowed => return false,
+            Verdict::Limited(retry_after) => (retry_after, false),
+            Verdict::Disconnect(retry_after) => (retry_after, true),
+        };
+
+        let retry_after_ms = retry_after.as_millis().max(1) as u64;
+        self.send_error(session_id, ErrorOutput::RateLimited { retry_after_ms })
+            .await;
+        // Repeat offenders lose their session too, so they can't resume their way back in
+        if disconnect {
+            println!(
+                "Session {} keeps hitting rate limits, disconnecting",
+                session_id
+            );
+            let outbox = self.outboxes.read().await.get(&session_id).cloned();
+            match outbox {
+                Some(outbox) => {
+                    self.disconnect(session_id, &outbox, CLOSE_POLICY_VIOLATION, "rate limited")
+                        .await
+                }
+                None => self.remove_session(session_id).await,
+            }
+        }
+        true
+    }
+
+    // Charge a new connection to its remote address, Err with how long until it may connect again
+    pub fn admit_connection(&self, address: IpAddr) -> Result<(), Duration> {
+        self.rate_limiter.admit_connection(address)
+    }
+
+    // Close a connection for breaking the rules, its session ends with it so it can't be resumed
+    // Nothing is removed when the connection was already replaced, ex: by a resume
+    pub async fn disconnect(
+        &self,
+        session_id: Uuid,
+        outbox: &Arc<Outbox>,
+        code: u16,
+        reason: &'static str,
+    ) {
+        outbox.close(code, reason);
+        let current = self
+            .outboxes
+            .read()
+            .await
+            .get(&session_id)
+            .is_some_and(|current| Arc::ptr_eq(current, outbox));
+        if current {
+            self.remove_session(session_id).await;
         }
     }
 
-    // Generate a thread-safe listener from the websocket
-    pub fn subscribe(&self) -> broadcast::Receiver<ResponsePacket> {
-        return self.response_sender.subscribe();
+    // Tell a connection that a frame it sent couldn't be read
+    pub async fn reject_input(&self, session_id: Uuid, detail: String) {
+        self.send_error(session_id, ErrorOutput::InvalidInput { detail })
+            .await;
+    }
+
+    // Called when a connection ends, joined sessions are held for resume_grace before they are removed
+    // Only the session's current connection counts, one that was already evicted or taken over by a resume changes nothing
+    pub async fn handle_disconnect(&self, session_id: Uuid, outbox: &Arc<Outbox>) {
+        let resumable = self.resume_grace.is_some()
+            && self.store.read().await.session_get(session_id).is_some();
+        {
+            let mut outboxes = self.outboxes.write().await;
+            let current = outboxes
+                .get(&session_id)
+                .is_some_and(|current| Arc::ptr_eq(current, outbox));
+            if !current {
+                return;
+            }
+            if resumable {
+                // Outputs collect in a fresh buffer, the old outbox still belongs to the dying connection's writer
+                let buffer = Arc::new(Outbox::new(self.outbox_capacity, self.slow_consumer_policy));
+                outboxes.insert(session_id, buffer);
+                self.detached
+                    .write()
+                    .await
+                    .insert(session_id, Instant::now());
+            }
+        }
+        outbox.close(CLOSE_GOING_AWAY, "disconnected");
+
+        if !resumable {
+            self.remove_session(session_id).await;
+            return;
+        }
+        println!("Session {} detached, waiting for it to resume", session_id);
     }
 
-    // Remove user on disconnect
-    pub async fn handle_disconnect(&self, session_id: Uuid) {
-        if self.sessions.write().await.remove(&session_id).is_some() {
+    // Remove the session for good and let everyone else know
+    async fn remove_session(&self, session_id: Uuid) {
+        if let Some(outbox) = self.outboxes.write().await.remove(&session_id) {
+            outbox.close(CLOSE_GOING_AWAY, "disconnected");
+        }
+        self.detached.write().await.remove(&session_id);
+        self.resume_tokens
+            .write()
+            .await
+            .retain(|_, resumes| *resumes != session_id);
+        self.nonces.write().await.remove(&session_id);
+        self.typing
+            .write()
+            .await
+            .retain(|(typing_id, _), _| *typing_id != session_id);
+        self.rate_limiter.forget_session(session_id);
+        self.authenticated.write().await.remove(&session_id);
+
+        let removed = self.store.write().await.session_remove(session_id);
+        let removed = removed.unwrap_or_else(|err| {
+            println!("Failed to remove session {}: {}", session_id, err);
+            None
+        });
+        if let Some(session) = removed {
             self.send_except_session_id(
                 session_id,
                 Output::UserDisconnect(UserDiscconnectOutput::new(session_id)),
             )
             .await;
+
+            for waitlist in self.waitlists.write().await.values_mut() {
+                waitlist.retain(|waiting_id| *waiting_id != session_id);
+            }
+            for channel_id in session.channels {
+                self.admit_waitlisted(channel_id).await;
+            }
+        }
+    }
+
+    // Detached sessions that didn't resume within the grace window are removed
+    async fn process_resume_grace(&self) {
+        let resume_grace = match self.resume_grace {
+            Some(resume_grace) => resume_grace,
+            None => return futures::future::pending().await,
+        };
+
+        loop {
+            time::sleep(RESUME_SWEEP_INTERVAL.min(resume_grace)).await;
+
+            let expired: Vec<Uuid> = self
+                .detached
+                .read()
+                .await
+                .iter()
+                .filter(|(_, detached_at)| detached_at.elapsed() >= resume_grace)
+                .map(|(session_id, _)| *session_id)
+                .collect();
+            for session_id in expired {
+                println!("Session {} did not resume in time", session_id);
+                self.remove_session(session_id).await;
+            }
+        }
+    }
+
+    // Stop typing indicators whose session went quiet, and forget ones that are past their throttle
+    async fn process_typing_expiry(&self) {
+        loop {
+            time::sleep(TYPING_SWEEP_INTERVAL).await;
+
+            let now = Instant::now();
+            let mut expired = Vec::new();
+            self.typing.write().await.retain(|key, typing| {
+                if typing
+                    .expires_at
+                    .is_some_and(|expires_at| expires_at <= now)
+                {
+                    typing.expires_at = None;
+                    expired.push(*key);
+                }
+                typing.expires_at.is_some()
+                    || now.duration_since(typing.relayed_at) < TYPING_THROTTLE
+            });
+            for (session_id, channel_id) in expired {
+                self.send_channel_except_session_id(
+                    channel_id,
+                    session_id,
+                    Output::TypingStopped(TypingOutput::new(channel_id, session_id)),
+                )
+                .await;
+            }
         }
     }
 
@@ -111,201 +647,2200 @@ impl Holocaster {
     async fn handle_message(&self, request_packet: RequestPacket) {
         match request_packet.body {
             Input::Join(body) => self.process_join(request_packet.session_id, body).await,
+            Input::Message(body) if holo_commands::is_command(&body.body) => {
+                self.process_command(request_packet.session_id, body).await
+            }
             Input::Message(body) => self.process_message(request_packet.session_id, body).await,
+            Input::JoinChannel(body) => {
+                self.process_join_channel(request_packet.session_id, body)
+                    .await
+            }
+            Input::LeaveChannel(body) => {
+                self.process_leave_channel(request_packet.session_id, body)
+                    .await
+            }
+            Input::CreateChannel(body) => {
+                self.process_create_channel(request_packet.session_id, body)
+                    .await
+            }
+            Input::ListChannels(body) => {
+                self.process_list_channels(request_packet.session_id, body)
+                    .await
+            }
+            Input::FetchHistory(body) => {
+                self.process_fetch_history(request_packet.session_id, body)
+                    .await
+            }
+            Input::EditMessage(body) => {
+                self.process_edit_message(request_packet.session_id, body)
+                    .await
+            }
+            Input::DeleteMessage(body) => {
+                self.process_delete_message(request_packet.session_id, body)
+                    .await
+            }
+            Input::ChangeName(body) => {
+                self.process_change_name(request_packet.session_id, body)
+                    .await
+            }
+            Input::DirectMessage(body) => {
+                self.process_direct_message(request_packet.session_id, body)
+                    .await
+            }
+            Input::ListUsers(body) => {
+                self.process_list_users(request_packet.session_id, body)
+                    .await
+            }
+            Input::SetStatus(body) => {
+                self.process_set_status(request_packet.session_id, body)
+                    .await
+            }
+            Input::TypingStart(body) => {
+                self.process_typing_start(request_packet.session_id, body)
+                    .await
+            }
+            Input::TypingStop(body) => {
+                self.process_typing_stop(request_packet.session_id, body)
+                    .await
+            }
+            Input::MarkRead(body) => {
+                self.process_mark_read(request_packet.session_id, body)
+                    .await
+            }
+            Input::AddReaction(body) => {
+                self.process_reaction(request_packet.session_id, body, true)
+                    .await
+            }
+            Input::RemoveReaction(body) => {
+                self.process_reaction(request_packet.session_id, body, false)
+                    .await
+            }
+            Input::FetchThread(body) => {
+                self.process_fetch_thread(request_packet.session_id, body)
+                    .await
+            }
+            Input::FetchUnread(body) => {
+                self.process_fetch_unread(request_packet.session_id, body)
+                    .await
+            }
         }
     }
 
     // Handle a user joining the stream
     async fn process_join(&self, session_id: Uuid, body: JoinEvent) {
-        // TODO: add validation!
-        // I don't have validation right now because we are assuming all the data provided by Holonet is good to go!
         println!("processing join event!");
+        let user_name = String::from(body.user_name.trim());
+        if !is_valid_user_name(&user_name) {
+            self.send_error(session_id, ErrorOutput::InvalidName).await;
+            return;
+        }
 
-        // Track the client with a session object
-        let session = Session::new(session_id, &body.user_name);
-        self.sessions
-            .write()
-            .await
-            .insert(session_id, session.clone());
-        println!("saved session event!");
+        // Track the client with a session object, every session starts out in the default holonet channel
+        let mut session = Session::new(session_id, &user_name);
+        session.join_channel(
+            DEFAULT_HOLONET_CHANNEL.id,
+            self.last_seq(DEFAULT_HOLONET_CHANNEL.id).await,
+        );
 
+        // Check and claim the name under one lock so two sessions can't race for it
+        // A name from an auth token wins over a guest using it, the guest is renamed instead
+        let claimed = {
+            let authenticated = self.authenticated.read().await;
+            let mut store = self.store.write().await;
+            let holder_id = store
+                .sessions()
+                .find(|holder| holder.id != session_id && holder.has_name(&user_name))
+                .map(|holder| holder.id);
+            if store.session_get(session_id).is_some() {
+                Err(ErrorOutput::AlreadyJoined)
+            } else {
+                match holder_id {
+                    // The client is free to retry with another name
+                    Some(holder_id)
+                        if !authenticated.contains(&session_id)
+                            || authenticated.contains(&holder_id) =>
+                    {
+                        Err(ErrorOutput::NameTaken)
+                    }
+                    guest_id => {
+                        claim_name(store.as_mut(), session.clone(), guest_id).map_err(|err| {
+                            println!("Failed to save session {}: {}", session_id, err);
+                            ErrorOutput::ServerError
+                        })
+                    }
+                }
+            }
+        };
+        let renamed_guest = match claimed {
+            Ok(renamed_guest) => renamed_guest,
+            Err(error) => {
+                self.send_error(session_id, error).await;
+                return;
+            }
+        };
+        if let Some((guest, previous_name)) = renamed_guest {
+            let guest_id = guest.id;
+            let output = Output::UserRenamed(UserRenamedOutput::new(guest, previous_name));
+            self.send_session_id(guest_id, output.clone()).await;
+            self.send_except_session_id(guest_id, output).await;
+        }
         // Send payload of info to the user that just joined
-        let channels = self.get_user_channels().await.unwrap();
-        let mut output_packet = UserJoinedOutput {
-            channels: vec![],
-            user: UserModelResponse {
-                id: session_id,
-                name: String::from(body.user_name),
-            },
+        let output_packet = UserJoinedOutput::new(
+            self.get_user_channels(&session).await,
+            UserModelResponse::from(&session),
+        );
+
+        // Only the user that joined gets a resume token
+        let resume_token = match self.resume_grace {
+            Some(_) => Some(self.issue_resume_token(session_id).await),
+            None => None,
         };
 
-        // TODO: this needs to be cleaned up
-        // sending messages off
-        for channel in channels.iter() {
-            println!("sending a response to a chanel!");
-            output_packet.channels.push(ChannelModelResponse {
-                id: channel.id,
-                name: channel.name.clone(),
-            });
-        }
+        // Unread counts are private to the user that joined as well
+        let own_packet = UserJoinedOutput {
+            channels: self.get_unread_channels(&session).await,
+            ..output_packet.clone()
+        };
 
         println!("Notifying session confirmation of join");
-        self.send_session_id(session_id, Output::UserJoined(output_packet.clone()))
-            .await;
+        self.send_session_id(
+            session_id,
+            Output::UserJoined(
+                own_packet
+                    .with_resume_token(resume_token)
+                    .with_rosters(self.get_rosters(&session).await),
+            ),
+        )
+        .await;
         println!("Notifying all other users confirmation of join");
         self.send_except_session_id(session_id, Output::UserJoined(output_packet))
             .await;
-    }
 
-    //  this function is to get the current channel the user is listening to
-    // Maybe this should return back an array instead of just one channel?
-    // TODO: We only support the channel in the '0' position for now
-    // TODO: this is super sloppy
-    async fn get_user_channels(&self) -> Option<Vec<Channel>> {
-        let channels = self.channels.read().await;
+        // Catch the user up on what they missed
+        for channel_id in session.channels.iter() {
+            self.send_history(
+                session_id,
+                *channel_id,
+                MessageCursor::Latest,
+                DEFAULT_HISTORY_PAGE_SIZE,
+            )
+            .await;
+        }
+    }
 
-        if channels.len() > 0 {
-            // for channel in channels.iter() {}
-            return Some(vec![channels[0].clone()]);
+    // Handle a user picking a new display name, authenticated users keep the name from their token
+    async fn process_change_name(&self, session_id: Uuid, body: ChangeNameEvent) {
+        if self.authenticated.read().await.contains(&session_id) {
+            self.send_error(session_id, ErrorOutput::Forbidden).await;
+            return;
+        }
+        let user_name = String::from(body.user_name.trim());
+        if !is_valid_user_name(&user_name) {
+            self.send_error(session_id, ErrorOutput::InvalidName).await;
+            return;
         }
-        None
+
+        let renamed = {
+            let mut store = self.store.write().await;
+            match store.session_get(session_id).cloned() {
+                _ if is_name_taken(store.as_ref(), session_id, &user_name) => {
+                    Err(ErrorOutput::NameTaken)
+                }
+                Some(mut session) => {
+                    let previous_name = std::mem::replace(&mut session.name, user_name.clone());
+                    store
+                        .session_save(session.clone())
+                        .map(|_| (previous_name, session))
+                        .map_err(|err| {
+                            println!("Failed to save session {}: {}", session_id, err);
+                            ErrorOutput::ServerError
+                        })
+                }
+                None => Err(ErrorOutput::InvalidSession),
+            }
+        };
+
+        let (previous_name, session) = match renamed {
+            Ok(renamed) => renamed,
+            Err(error) => {
+                self.send_error(session_id, error).await;
+                return;
+            }
+        };
+        let output = Output::UserRenamed(UserRenamedOutput::new(
+            UserModelResponse::from(&session),
+            previous_name,
+        ));
+        self.send_session_id(session_id, output.clone()).await;
+        self.send_except_session_id(session_id, output).await;
     }
 
-    // async fn get_messages_from_channel(&self, channel: Channel) -> Vec<Message> {}
+    // Handle a user asking who is online, or who is in one of their channels
+    async fn process_list_users(&self, session_id: Uuid, body: ListUsersEvent) {
+        let session = match self.get_session(session_id).await {
+            Some(session) => session,
+            None => return,
+        };
 
-    // Handle a user sending a message to the stream
-    async fn process_message(&self, session_id: Uuid, message: MessageEvent) {
-        // Verify authentication of the user
-        let user = if let Some(user) = self.sessions.read().await.get(&session_id) {
-            user.clone()
-        } else {
-            self.send_error(session_id, ErrorOutput::InvalidSession)
+        if body
+            .channel_id
+            .is_some_and(|channel_id| !session.is_member(channel_id))
+        {
+            self.send_error(session_id, ErrorOutput::InvalidChannel)
                 .await;
             return;
+        }
+
+        let users = roster(self.store.read().await.as_ref(), body.channel_id);
+        self.send_session_id(
+            session_id,
+            Output::UserList(UserListOutput::new(body.channel_id, users)),
+        )
+        .await;
+    }
+
+    // Handle a user changing their presence, everyone online hears about it
+    async fn process_set_status(&self, session_id: Uuid, body: SetStatusEvent) {
+        let changed = self
+            .update_session(session_id, |session| {
+                let changed = session.status != body.status;
+                session.status = body.status;
+                (changed, UserModelResponse::from(&*session))
+            })
+            .await;
+
+        let (changed, user) = match changed {
+            Some(changed) => changed,
+            None => {
+                self.send_error(session_id, ErrorOutput::InvalidSession)
+                    .await;
+                return;
+            }
         };
 
-        if message.body.is_empty() || message.body.len() > MAX_MESSAGE_BODY_LENGTH {
-            self.send_error(session_id, ErrorOutput::InvalidMessageRequest)
-                .await;
+        let output = Output::StatusChanged(StatusChangedOutput::new(user));
+        self.send_session_id(session_id, output.clone()).await;
+        // Setting the status it already has is only confirmed to the session itself
+        if changed {
+            self.send_except_session_id(session_id, output).await;
+        }
+    }
+
+    // Handle a user catching up on one of their channels
+    async fn process_mark_read(&self, session_id: Uuid, body: MarkReadEvent) {
+        if !self.require_member(session_id, body.channel_id).await {
             return;
         }
 
-        let message = Message::new(
-            Uuid::new_v4(),
-            session_id,
-            user.clone(),
-            &message.body,
-            Utc::now(),
-        );
+        let seq = self
+            .store
+            .read()
+            .await
+            .channel_get(body.channel_id)
+            .and_then(|channel| channel.message_get_by_id(body.message_id))
+            .map(|message| message.seq);
+        let seq = match seq {
+            Some(seq) => seq,
+            None => {
+                self.send_error(session_id, ErrorOutput::MessageNotFound)
+                    .await;
+                return;
+            }
+        };
 
-        // Send the message to the DB
-        let mut channels = self.channels.write().await;
-        channels[0].message_add(message.clone());
+        let marked = self
+            .update_session(session_id, |session| {
+                (session.mark_read(body.channel_id, seq), session.clone())
+            })
+            .await;
+        let (advanced, session) = match marked {
+            Some(marked) => marked,
+            None => return,
+        };
 
-        let response_packet = UserMessageOutput::new(
-            MessageModelResponse {
-                id: user.id,
-                body: message.body,
-                created_by: user.id,
-                created_at: message.created_at,
-            },
-            ChannelModelResponse {
-                id: channels[0].id,
-                name: channels[0].name.clone(),
-            },
-        );
+        // The session's new unread count for the channel, whether or not its cursor moved
+        let channels = self
+            .get_unread_channels(&session)
+            .await
+            .into_iter()
+            .filter(|channel| channel.id == body.channel_id)
+            .collect();
+        self.send_session_id(
+            session_id,
+            Output::UnreadCounts(UnreadCountsOutput::new(channels)),
+        )
+        .await;
+        if advanced && self.broadcast_read_receipts {
+            self.send_channel_except_session_id(
+                body.channel_id,
+                session_id,
+                Output::ReadReceipt(ReadReceiptOutput::new(
+                    body.channel_id,
+                    session_id,
+                    body.message_id,
+                    seq,
+                )),
+            )
+            .await;
+        }
+    }
 
-        // output the message to the client as confirmation
+    // Handle a user asking how much they have left to read
+    async fn process_fetch_unread(&self, session_id: Uuid, body: FetchUnreadEvent) {
+        let session = match self.get_session(session_id).await {
+            Some(session) => session,
+            None => return,
+        };
+        if body
+            .channel_id
+            .is_some_and(|channel_id| !session.is_member(channel_id))
+        {
+            self.send_error(session_id, ErrorOutput::InvalidChannel)
+                .await;
+            return;
+        }
+
+        let channels = self
+            .get_unread_channels(&session)
+            .await
+            .into_iter()
+            .filter(|channel| {
+                body.channel_id
+                    .is_none_or(|channel_id| channel.id == channel_id)
+            })
+            .collect();
         self.send_session_id(
-            session_id.clone(),
-            Output::UserMessage(response_packet.clone()),
+            session_id,
+            Output::UnreadCounts(UnreadCountsOutput::new(channels)),
         )
         .await;
+    }
+
+    // Handle a user typing in one of their channels, repeats only push the expiry back
+    async fn process_typing_start(&self, session_id: Uuid, body: TypingEvent) {
+        if !self.require_member(session_id, body.channel_id).await {
+            return;
+        }
+
+        let relay = {
+            let now = Instant::now();
+            let mut typing = self.typing.write().await;
+            match typing.get_mut(&(session_id, body.channel_id)) {
+                Some(typing) if typing.expires_at.is_some() => {
+                    typing.expires_at = Some(now + TYPING_TIMEOUT);
+                    false
+                }
+                // Toggling typing on and off faster than the throttle is dropped
+                Some(typing) if now.duration_since(typing.relayed_at) < TYPING_THROTTLE => false,
+                _ => {
+                    typing.insert(
+                        (session_id, body.channel_id),
+                        Typing {
+                            expires_at: Some(now + TYPING_TIMEOUT),
+                            relayed_at: now,
+                        },
+                    );
+                    true
+                }
+            }
+        };
 
-        // send to the rest of the clients
-        self.send_except_session_id(session_id, Output::Message(response_packet))
+        if relay {
+            self.send_channel_except_session_id(
+                body.channel_id,
+                session_id,
+                Output::TypingStarted(TypingOutput::new(body.channel_id, session_id)),
+            )
             .await;
+        }
     }
 
-    async fn process_keep_alive(&self) {
-        let alive_interval = self.alive_interval;
-        loop {
-            println!("pinging all session!");
-            time::sleep(alive_interval.unwrap()).await;
-            self.send(Output::KeepAliveTick).await;
+    async fn process_typing_stop(&self, session_id: Uuid, body: TypingEvent) {
+        if self.require_member(session_id, body.channel_id).await {
+            self.stop_typing(session_id, body.channel_id).await;
         }
     }
 
-    /////////////////////
-    //  The following seeries of send() functions handle the logic of directing responses to the response_sender stream
-    // TODO: if we want to make this horizontally scalable we need a pub/sub solution 
-    // to "echo" messeages to/from other services in the cluster.. for this use case probably redis
-    /////////////////////
-    async fn send(&self, output: Output) {
-        println!("!!! Attempting to send !!!");
-        if self.response_sender.receiver_count() == 0 {
-            println!("Aborting send - no clients listening!");
-            return;
+    // Relay typing-stopped if the session was typing, ex: it stopped or posted its message
+    async fn stop_typing(&self, session_id: Uuid, channel_id: Uuid) {
+        let was_typing = self
+            .typing
+            .write()
+            .await
+            .get_mut(&(session_id, channel_id))
+            .and_then(|typing| typing.expires_at.take())
+            .is_some();
+        if was_typing {
+            self.send_channel_except_session_id(
+                channel_id,
+                session_id,
+                Output::TypingStopped(TypingOutput::new(channel_id, session_id)),
+            )
+            .await;
         }
+    }
+
+    // False, and answered with invalid-channel, when the session isn't in the channel
+    async fn require_member(&self, session_id: Uuid, channel_id: Uuid) -> bool {
+        let session = match self.get_session(session_id).await {
+            Some(session) => session,
+            None => return false,
+        };
+        if !session.is_member(channel_id) {
+            self.send_error(session_id, ErrorOutput::InvalidChannel)
+                .await;
+            return false;
+        }
+        true
+    }
 
-        println!("We have a recievers, sending out!");
+    // Handle a registered user joining an existing channel
+    async fn process_join_channel(&self, session_id: Uuid, body: JoinChannelEvent) {
+        let channel = match self.get_channel(body.channel_id).await {
+            Some(channel) => channel,
+            None => {
+                self.send_error(session_id, ErrorOutput::InvalidChannel)
+                    .await;
+                return;
+            }
+        };
 
-        let sessions = self.sessions.read().await;
-        for (user_id, _session) in sessions.iter() {
-            println!("flushing out messages to session: {}", user_id);
-            self.response_sender
-                .send(ResponsePacket::new(*user_id, *user_id, output.clone()))
-                .unwrap();
+        match self.take_seat(session_id, &channel, body.wait).await {
+            Some((ChannelSeat::Joined, session)) => {
+                self.announce_channel_joined(&session, channel, true).await
+            }
+            Some((ChannelSeat::AlreadyMember, session)) => {
+                self.announce_channel_joined(&session, channel, false).await
+            }
+            Some((ChannelSeat::Waitlisted(position), _)) => {
+                self.send_session_id(
+                    session_id,
+                    Output::ChannelWaitlisted(ChannelWaitlistedOutput::new(channel, position)),
+                )
+                .await
+            }
+            Some((ChannelSeat::Full, _)) => {
+                self.send_error(session_id, ErrorOutput::ChannelFull).await
+            }
+            None => {
+                self.send_error(session_id, ErrorOutput::InvalidSession)
+                    .await
+            }
         }
     }
 
-    async fn send_session_id(&self, session_id: Uuid, output: Output) {
-        // println!("!!! send_session_id out messages to session: {}", session_id);
-        if self.response_sender.receiver_count() == 0 {
-            println!("Aborting send_session_id - no clients listening!");
-            return;
+    // Members are counted and seated under one lock so concurrent joins can't overfill a channel
+    async fn take_seat(
+        &self,
+        session_id: Uuid,
+        channel: &ChannelModelResponse,
+        wait: bool,
+    ) -> Option<(ChannelSeat, Session)> {
+        let (seat, session) = {
+            let mut store = self.store.write().await;
+            let member_count = count_members(store.as_ref(), channel.id);
+            let last_seq = store
+                .channel_get(channel.id)
+                .map_or(0, |channel| channel.last_seq);
+            let mut session = store.session_get(session_id)?.clone();
+
+            let seat = if session.is_member(channel.id) {
+                ChannelSeat::AlreadyMember
+            } else if !is_channel_full(channel, member_count) {
+                session.join_channel(channel.id, last_seq);
+                ChannelSeat::Joined
+            } else if wait {
+                let mut waitlists = self.waitlists.write().await;
+                let waitlist = waitlists.entry(channel.id).or_default();
+                let position = match waitlist.iter().position(|id| *id == session_id) {
+                    Some(index) => index,
+                    None => {
+                        waitlist.push_back(session_id);
+                        waitlist.len() - 1
+                    }
+                };
+                ChannelSeat::Waitlisted(position + 1)
+            } else {
+                ChannelSeat::Full
+            };
+            if let ChannelSeat::Joined = seat {
+                if let Err(err) = store.session_save(session.clone()) {
+                    println!("Failed to save session {}: {}", session_id, err);
+                    return None;
+                }
+            }
+            (seat, session)
+        };
+        Some((seat, session))
+    }
+
+    // Hand a freed seat to the longest waiting session
+    async fn admit_waitlisted(&self, channel_id: Uuid) {
+        let channel = match self.get_channel(channel_id).await {
+            Some(channel) => channel,
+            None => return,
+        };
+
+        loop {
+            let next = {
+                let mut waitlists = self.waitlists.write().await;
+                let next = waitlists
+                    .get_mut(&channel_id)
+                    .and_then(|waitlist| waitlist.pop_front());
+                if waitlists.get(&channel_id).is_some_and(VecDeque::is_empty) {
+                    waitlists.remove(&channel_id);
+                }
+                match next {
+                    Some(next) => next,
+                    None => return,
+                }
+            };
+
+            match self.take_seat(next, &channel, false).await {
+                Some((ChannelSeat::Joined, session)) => {
+                    self.announce_channel_joined(&session, channel, true).await;
+                    return;
+                }
+                Some((ChannelSeat::Full, _)) => {
+                    // Someone else took the seat first, keep our place at the front
+                    self.waitlists
+                        .write()
+                        .await
+                        .entry(channel_id)
+                        .or_default()
+                        .push_front(next);
+                    return;
+                }
+                // Already a member or no longer connected, try the next in line
+                _ => continue,
+            }
         }
+    }
 
-        let sessions = self.sessions.read().await;
-        sessions
-            .values()
-            .filter(|session| session.id != session_id)
-            .for_each(|session| {
-                println!("send_session_id flushing out messages to session: {}", session.id);
-                self.response_sender
-                    .send(ResponsePacket::new(session.id, session_id, output.clone()))
-                    .unwrap();
-            });
+    // Confirm the join to the session with the latest history, and let the channel know
+    async fn announce_channel_joined(
+        &self,
+        session: &Session,
+        channel: ChannelModelResponse,
+        newly_joined: bool,
+    ) {
+        let channel_id = channel.id;
+        let output_packet = ChannelJoinedOutput::new(channel, UserModelResponse::from(session));
+
+        // Only the session that joined hears its unread count
+        let unread_count = self
+            .store
+            .read()
+            .await
+            .channel_get(channel_id)
+            .map(|channel| channel.count_unread(session.read_seq(channel_id), session.id));
+        let own_packet = ChannelJoinedOutput {
+            channel: output_packet
+                .channel
+                .clone()
+                .with_unread_count(unread_count),
+            ..output_packet.clone()
+        };
+        self.send_session_id(session.id, Output::ChannelJoined(own_packet))
+            .await;
+        self.send_history(
+            session.id,
+            channel_id,
+            MessageCursor::Latest,
+            DEFAULT_HISTORY_PAGE_SIZE,
+        )
+        .await;
+
+        // Re-joining a channel is a no-op for everyone else
+        if newly_joined {
+            self.send_channel_except_session_id(
+                channel_id,
+                session.id,
+                Output::ChannelJoined(output_packet),
+            )
+            .await;
+        }
     }
 
-    // Send a message to everyone but the specified session ID
-    async fn send_except_session_id(&self, session_id: Uuid, output: Output) {
-        println!(" send_except_session_id out messages to session: {}", session_id);
-        if self.response_sender.receiver_count() == 0 {
-            println!("Aborting send_session_id - no clients listening!");
+    // Handle a user leaving one of their channels
+    async fn process_leave_channel(&self, session_id: Uuid, body: LeaveChannelEvent) {
+        let left = match self
+            .update_session(session_id, |session| session.leave_channel(body.channel_id))
+            .await
+        {
+            Some(left) => left,
+            None => {
+                self.send_error(session_id, ErrorOutput::InvalidSession)
+                    .await;
+                return;
+            }
+        };
+
+        let output = Output::ChannelLeft(ChannelLeftOutput::new(body.channel_id, session_id));
+        if !left {
+            // Leaving a channel we are still waiting on just gives up the place in line
+            if self.leave_waitlist(session_id, body.channel_id).await {
+                self.send_session_id(session_id, output).await;
+            } else {
+                self.send_error(session_id, ErrorOutput::InvalidChannel)
+                    .await;
+            }
             return;
         }
 
-        let sessions = self.sessions.read().await;
-        sessions
-            .values()
-            .filter(|session| session.id == session_id)
-            .for_each(|session| {
-                println!("send_except_session_id flushing out messages to session: {}", session.id);
-                self.response_sender
-                    .send(ResponsePacket::new(session.id, session_id, output.clone()))
-                    .unwrap();
-            });
+        self.typing
+            .write()
+            .await
+            .remove(&(session_id, body.channel_id));
+        self.send_session_id(session_id, output.clone()).await;
+        self.send_channel_except_session_id(body.channel_id, session_id, output)
+            .await;
+        self.admit_waitlisted(body.channel_id).await;
     }
 
-    async fn send_error(&self, session_id: Uuid, error: ErrorOutput) {
-        println!("sending errors!");
-        self.send_session_id(session_id, Output::Error(error)).await;
+    async fn leave_waitlist(&self, session_id: Uuid, channel_id: Uuid) -> bool {
+        let mut waitlists = self.waitlists.write().await;
+        let waitlist = match waitlists.get_mut(&channel_id) {
+            Some(waitlist) => waitlist,
+            None => return false,
+        };
+        let waiting = waitlist.len();
+        waitlist.retain(|waiting_id| *waiting_id != session_id);
+        waitlist.len() != waiting
     }
-}
 
-impl Default for Holocaster {
-    fn default() -> Self {
-        Self::new(HolocasterConfig::default())
+    // Handle a user creating a new channel, the creator is automatically a member
+    async fn process_create_channel(&self, session_id: Uuid, body: CreateChannelEvent) {
+        if self.get_session(session_id).await.is_none() {
+            return;
+        }
+
+        let name = body.name.trim();
+        let max_members = body.max_members.or(self.max_channel_members);
+        if name.is_empty() || name.len() > MAX_CHANNEL_NAME_LENGTH || max_members == Some(0) {
+            self.send_error(session_id, ErrorOutput::InvalidChannelRequest)
+                .await;
+            return;
+        }
+
+        let channel = Channel::new(Uuid::nil(), name, body.game_id.unwrap_or_default())
+            .with_max_members(max_members);
+        let output_packet = ChannelCreatedOutput::new(ChannelModelResponse::from(&channel));
+        let channel_id = channel.id;
+        let saved = self.store.write().await.channel_save(channel);
+        if let Err(err) = saved {
+            println!("Failed to save channel {}: {}", channel_id, err);
+            self.send_error(session_id, ErrorOutput::ServerError).await;
+            return;
+        }
+
+        // Nothing has been posted to a new channel yet
+        self.update_session(session_id, |session| session.join_channel(channel_id, 0))
+            .await;
+
+        self.send_session_id(session_id, Output::ChannelCreated(output_packet))
+            .await;
+    }
+
+    // Handle a user looking for channels to join, ex: one someone else just created
+    async fn process_list_channels(&self, session_id: Uuid, body: ListChannelsEvent) {
+        if self.get_session(session_id).await.is_none() {
+            return;
+        }
+
+        let mut channels: Vec<ChannelModelResponse> = self
+            .store
+            .read()
+            .await
+            .channels()
+            .filter(|channel| !channel.is_direct())
+            .filter(|channel| {
+                body.game_id
+                    .is_none_or(|game_id| channel.game_id == game_id)
+            })
+            .map(ChannelModelResponse::from)
+            .collect();
+        channels.sort_by(|a, b| a.name.cmp(&b.name));
+        self.send_session_id(
+            session_id,
+            Output::ChannelList(ChannelListOutput::new(channels)),
+        )
+        .await;
+    }
+
+    // Handle a user paging back through the history of one of their channels
+    async fn process_fetch_history(&self, session_id: Uuid, body: FetchHistoryEvent) {
+        let session = match self.get_session(session_id).await {
+            Some(session) => session,
+            None => return,
+        };
+
+        // Direct conversations have no members, only their two participants can read them
+        let participant = self
+            .store
+            .read()
+            .await
+            .channel_get(body.channel_id)
+            .is_some_and(|channel| channel.has_participant(session_id));
+        if !session.is_member(body.channel_id) && !participant {
+            self.send_error(session_id, ErrorOutput::InvalidChannel)
+                .await;
+            return;
+        }
+
+        let cursor = if let Some(seq) = body.after_seq {
+            MessageCursor::AfterSeq(seq)
+        } else if let Some(seq) = body.before_seq {
+            MessageCursor::BeforeSeq(seq)
+        } else if let Some(message_id) = body.before_id {
+            MessageCursor::BeforeId(message_id)
+        } else if let Some(before) = body.before {
+            MessageCursor::BeforeTime(before)
+        } else {
+            MessageCursor::Latest
+        };
+        let limit = body.limit.unwrap_or(DEFAULT_HISTORY_PAGE_SIZE);
+
+        self.send_history(session_id, body.channel_id, cursor, limit)
+            .await;
+    }
+
+    // Send a single page of channel history to a session
+    async fn send_history(
+        &self,
+        session_id: Uuid,
+        channel_id: Uuid,
+        cursor: MessageCursor,
+        limit: usize,
+    ) {
+        let history = self
+            .store
+            .read()
+            .await
+            .channel_get(channel_id)
+            .map(|channel| {
+                channel
+                    .get_messages_page(cursor, limit)
+                    .map(|(messages, has_more)| {
+                        HistoryOutput::new(
+                            ChannelModelResponse::from(channel),
+                            messages.iter().map(MessageModelResponse::from).collect(),
+                            has_more,
+                        )
+                    })
+            });
+
+        match history {
+            Some(Some(history)) => {
+                self.send_session_id(session_id, Output::History(history))
+                    .await
+            }
+            Some(None) => {
+                self.send_error(session_id, ErrorOutput::InvalidMessageRequest)
+                    .await
+            }
+            None => {
+                self.send_error(session_id, ErrorOutput::InvalidChannel)
+                    .await
+            }
+        }
+    }
+
+    // Look up a session, notifying the caller if it does not exist
+    async fn get_session(&self, session_id: Uuid) -> Option<Session> {
+        let session = self.store.read().await.session_get(session_id).cloned();
+        if session.is_none() {
+            self.send_error(session_id, ErrorOutput::InvalidSession)
+                .await;
+        }
+        session
+    }
+
+    // Apply a change to a live session and save it, None when there is no such session or it couldn't be saved
+    async fn update_session<F, R>(&self, session_id: Uuid, update: F) -> Option<R>
+    where
+        F: FnOnce(&mut Session) -> R,
+    {
+        let mut store = self.store.write().await;
+        let mut session = store.session_get(session_id)?.clone();
+        let result = update(&mut session);
+        if let Err(err) = store.session_save(session) {
+            println!("Failed to save session {}: {}", session_id, err);
+            return None;
+        }
+        Some(result)
+    }
+
+    // Who is in each of the session's channels, the session itself included
+    async fn get_rosters(&self, session: &Session) -> Vec<ChannelRosterResponse> {
+        let store = self.store.read().await;
+        session
+            .channels
+            .iter()
+            .map(|channel_id| {
+                ChannelRosterResponse::new(*channel_id, roster(store.as_ref(), Some(*channel_id)))
+            })
+            .collect()
+    }
+
+    // Direct conversations can't be joined or posted to like a channel, so they are never found here
+    async fn get_channel(&self, channel_id: Uuid) -> Option<ChannelModelResponse> {
+        self.store
+            .read()
+            .await
+            .channel_get(channel_id)
+            .filter(|channel| !channel.is_direct())
+            .map(ChannelModelResponse::from)
+    }
+
+    // Get every channel the session is currently a member of
+    async fn get_user_channels(&self, session: &Session) -> Vec<ChannelModelResponse> {
+        self.store
+            .read()
+            .await
+            .channels()
+            .filter(|channel| session.is_member(channel.id))
+            .map(ChannelModelResponse::from)
+            .collect()
+    }
+
+    // The newest seq in a channel, 0 when it is empty or gone
+    async fn last_seq(&self, channel_id: Uuid) -> u64 {
+        self.store
+            .read()
+            .await
+            .channel_get(channel_id)
+            .map_or(0, |channel| channel.last_seq)
+    }
+
+    // The session's own channels, each with how many messages it hasn't read yet
+    async fn get_unread_channels(&self, session: &Session) -> Vec<ChannelModelResponse> {
+        self.store
+            .read()
+            .await
+            .channels()
+            .filter(|channel| session.is_member(channel.id))
+            .map(|channel| {
+                let unread_count = channel.count_unread(session.read_seq(channel.id), session.id);
+                ChannelModelResponse::from(channel).with_unread_count(Some(unread_count))
+            })
+            .collect()
+    }
+
+    // The byte limit is checked first so a huge body is never walked for graphemes
+    fn is_valid_message_body(&self, body: &str) -> bool {
+        !body.is_empty()
+            && body.len() <= self.max_message_bytes
+            && body
+                .graphemes(true)
+                .nth(self.max_message_graphemes)
+                .is_none()
+    }
+
+    // Handle a user sending a message to the stream
+    async fn process_message(&self, session_id: Uuid, message: MessageEvent) {
+        // Verify authentication of the user
+        let user = match self.get_session(session_id).await {
+            Some(user) => user,
+            None => return,
+        };
+
+        if !self.is_valid_message_body(&message.body) {
+            self.send_error(session_id, ErrorOutput::InvalidMessageRequest)
+                .await;
+            return;
+        }
+
+        // Users can only post to channels they belong to
+        if !user.is_member(message.channel_id) {
+            self.send_error(session_id, ErrorOutput::InvalidChannel)
+                .await;
+            return;
+        }
+
+        // A retry of a message we already posted is confirmed again instead of posted twice
+        if let Some(nonce) = &message.nonce {
+            if let Some(message_id) = self.find_nonce(session_id, nonce).await {
+                self.resend_message(session_id, message.channel_id, message_id)
+                    .await;
+                return;
+            }
+        }
+        let nonce = message.nonce.clone();
+        let parent_id = message.parent_id;
+        let mentions = find_mentions(self.store.read().await.as_ref(), &message.body);
+
+        let message = Message::new(
+            Uuid::new_v4(),
+            message.channel_id,
+            user,
+            &message.body,
+            Utc::now(),
+        )
+        .with_mentions(mentions);
+
+        let channel = match self.get_channel(message.channel_id).await {
+            Some(channel) => channel,
+            None => {
+                self.send_error(session_id, ErrorOutput::InvalidChannel)
+                    .await;
+                return;
+            }
+        };
+
+        let cluster_seq = match self.cluster_seq(message.channel_id).await {
+            Ok(cluster_seq) => cluster_seq,
+            Err(error) => {
+                self.send_error(session_id, error).await;
+                return;
+            }
+        };
+
+        // Send the message to the DB, numbering it under the same lock so sequence numbers are never handed out twice
+        // A reply bumps its parent's reply count under the same lock too
+        let saved = {
+            let mut store = self.store.write().await;
+            let parent = match parent_id {
+                Some(parent_id) => store
+                    .channel_get(message.channel_id)
+                    .and_then(|channel| channel.thread_root(parent_id))
+                    .cloned()
+                    .map(Some)
+                    .ok_or(ErrorOutput::MessageNotFound),
+                None => Ok(None),
+            };
+            parent.and_then(|mut parent| {
+                let seq = cluster_seq.unwrap_or_else(|| {
+                    store
+                        .channel_get(message.channel_id)
+                        .map_or(1, Channel::next_seq)
+                });
+                let message = message
+                    .with_seq(seq)
+                    .with_parent_id(parent.as_ref().map(|parent| parent.id));
+                if let Some(parent) = parent.as_mut() {
+                    parent.reply_count += 1;
+                }
+                store
+                    .message_save(message.clone())
+                    .and_then(|_| match &parent {
+                        Some(parent) => store.message_save(parent.clone()),
+                        None => Ok(()),
+                    })
+                    .map(|_| (message, parent))
+                    .map_err(|err| {
+                        println!("Failed to save message in {}: {}", channel.id, err);
+                        ErrorOutput::ServerError
+                    })
+            })
+        };
+        let (message, parent) = match saved {
+            Ok(saved) => saved,
+            Err(error) => {
+                self.send_error(session_id, error).await;
+                return;
+            }
+        };
+
+        if let Some(nonce) = nonce {
+            self.remember_nonce(session_id, nonce, message.id).await;
+        }
+        self.stop_typing(session_id, message.channel_id).await;
+
+        let response_packet = UserMessageOutput::new(MessageModelResponse::from(&message), channel);
+
+        // output the message to the client as confirmation
+        self.send_session_id(session_id, Output::UserMessage(response_packet.clone()))
+            .await;
+
+        // send to the rest of the channel members
+        self.send_channel_except_session_id(
+            message.channel_id,
+            session_id,
+            Output::Message(response_packet.clone()),
+        )
+        .await;
+
+        // Mentioned users are notified whether or not they are in the channel
+        for user_id in message
+            .mentions
+            .iter()
+            .filter(|&&user_id| user_id != session_id)
+        {
+            self.send_session_id(*user_id, Output::Mentioned(response_packet.clone()))
+                .await;
+        }
+
+        if let Some(parent) = parent {
+            self.send_channel(
+                parent.channel_id,
+                Output::ThreadUpdated(ThreadUpdatedOutput::new(&parent)),
+            )
+            .await;
+        }
+    }
+
+    // Run a slash command typed into one of the session's channels instead of posting it
+    async fn process_command(&self, session_id: Uuid, message: MessageEvent) {
+        let session = match self.get_session(session_id).await {
+            Some(session) => session,
+            None => return,
+        };
+        // Commands are held to the same limits as messages, /me would otherwise post anything
+        if !self.is_valid_message_body(&message.body) {
+            self.send_error(session_id, ErrorOutput::InvalidMessageRequest)
+                .await;
+            return;
+        }
+        let channel_id = message.channel_id;
+        if !session.is_member(channel_id) {
+            self.send_error(session_id, ErrorOutput::InvalidChannel)
+                .await;
+            return;
+        }
+
+        // Commands see a snapshot, so no lock is held while they run
+        let (sessions, channels) = {
+            let store = self.store.read().await;
+            let sessions: Vec<Session> = store.sessions().cloned().collect();
+            let channels: Vec<ChannelModelResponse> = store
+                .channels()
+                .filter(|channel| !channel.is_direct())
+                .map(ChannelModelResponse::from)
+                .collect();
+            (sessions, channels)
+        };
+        let context = CommandContext {
+            session_id,
+            user_name: &session.name,
+            channel_id,
+            registry: &self.commands,
+            sessions: &sessions,
+            channels: &channels,
+        };
+        let outcomes = self.commands.run(&context, &message.body);
+        let outcomes = match outcomes {
+            Ok(outcomes) => outcomes,
+            Err(reply) => {
+                self.send_system_message(session_id, channel_id, reply)
+                    .await;
+                return;
+            }
+        };
+
+        for outcome in outcomes {
+            self.apply_command_outcome(session_id, channel_id, outcome)
+                .await;
+        }
+    }
+
+    async fn apply_command_outcome(
+        &self,
+        session_id: Uuid,
+        channel_id: Uuid,
+        outcome: CommandOutcome,
+    ) {
+        match outcome {
+            CommandOutcome::Reply(body) => {
+                self.send_system_message(session_id, channel_id, body).await
+            }
+            CommandOutcome::Announce(body) => {
+                self.send_channel(
+                    channel_id,
+                    Output::SystemMessage(SystemMessageOutput::new(channel_id, body)),
+                )
+                .await
+            }
+            CommandOutcome::Notify {
+                session_id: notified_id,
+                body,
+            } => {
+                self.send_system_message(notified_id, channel_id, body)
+                    .await
+            }
+            CommandOutcome::Send(audience, output) => self.send_to(audience, *output).await,
+            CommandOutcome::ChangeName(user_name) => {
+                if self.authenticated.read().await.contains(&session_id) {
+                    let reply =
+                        String::from("Your name comes from your login and can't be changed");
+                    self.send_system_message(session_id, channel_id, reply)
+                        .await;
+                    return;
+                }
+                // Renaming by command counts against the same limit as change-name
+                let change_name = ChangeNameEvent { user_name };
+                if self
+                    .is_rate_limited(session_id, &Input::ChangeName(change_name.clone()))
+                    .await
+                {
+                    return;
+                }
+                self.process_change_name(session_id, change_name).await
+            }
+            CommandOutcome::JoinChannel(join_channel_id) => {
+                let join = JoinChannelEvent {
+                    channel_id: join_channel_id,
+                    wait: false,
+                };
+                // Joining by command counts against the same limit as join-channel
+                if self
+                    .is_rate_limited(session_id, &Input::JoinChannel(join.clone()))
+                    .await
+                {
+                    return;
+                }
+                self.process_join_channel(session_id, join).await
+            }
+            CommandOutcome::LeaveChannel => {
+                self.process_leave_channel(session_id, LeaveChannelEvent { channel_id })
+                    .await
+            }
+        }
+    }
+
+    // Send a page of replies to a message in one of the session's channels
+    async fn process_fetch_thread(&self, session_id: Uuid, body: FetchThreadEvent) {
+        if !self.require_member(session_id, body.channel_id).await {
+            return;
+        }
+
+        let limit = body.limit.unwrap_or(DEFAULT_HISTORY_PAGE_SIZE);
+        let thread = self
+            .store
+            .read()
+            .await
+            .channel_get(body.channel_id)
+            .and_then(|channel| {
+                let parent = channel.message_get_by_id(body.message_id)?;
+                let (replies, has_more) =
+                    channel.get_thread_page(parent.id, body.before_seq, limit);
+                Some(ThreadOutput::new(
+                    channel.id,
+                    MessageModelResponse::from(parent),
+                    replies.iter().map(MessageModelResponse::from).collect(),
+                    has_more,
+                ))
+            });
+
+        match thread {
+            Some(thread) => {
+                self.send_session_id(session_id, Output::Thread(thread))
+                    .await
+            }
+            None => {
+                self.send_error(session_id, ErrorOutput::MessageNotFound)
+                    .await
+            }
+        }
+    }
+
+    // Handle a user whispering to another user, the message is kept in their direct conversation
+    async fn process_direct_message(&self, session_id: Uuid, body: DirectMessageEvent) {
+        let user = match self.get_session(session_id).await {
+            Some(user) => user,
+            None => return,
+        };
+
+        if !self.is_valid_message_body(&body.body) || body.user_id == session_id {
+            self.send_error(session_id, ErrorOutput::InvalidMessageRequest)
+                .await;
+            return;
+        }
+
+        // Only this node's sessions are known, in cluster mode a user on another node can't be whispered to
+        if self.store.read().await.session_get(body.user_id).is_none() {
+            self.send_error(session_id, ErrorOutput::InvalidSession)
+                .await;
+            return;
+        }
+
+        let conversation_id = channel::direct_conversation_id(session_id, body.user_id);
+        if let Some(nonce) = &body.nonce {
+            if let Some(message_id) = self.find_nonce(session_id, nonce).await {
+                self.resend_message(session_id, conversation_id, message_id)
+                    .await;
+                return;
+            }
+        }
+
+        let message = Message::new(
+            Uuid::new_v4(),
+            conversation_id,
+            user,
+            &body.body,
+            Utc::now(),
+        );
+
+        let cluster_seq = match self.cluster_seq(conversation_id).await {
+            Ok(cluster_seq) => cluster_seq,
+            Err(error) => {
+                self.send_error(session_id, error).await;
+                return;
+            }
+        };
+
+        // The conversation is created by its first message
+        let saved = {
+            let mut store = self.store.write().await;
+            let created = match store.channel_get(conversation_id) {
+                Some(_) => Ok(()),
+                None => store.channel_save(Channel::direct(session_id, body.user_id)),
+            };
+            let seq = cluster_seq.unwrap_or_else(|| {
+                store
+                    .channel_get(conversation_id)
+                    .map_or(1, Channel::next_seq)
+            });
+            let message = message.with_seq(seq);
+            created
+                .and_then(|_| store.message_save(message.clone()))
+                .map(|_| message)
+        };
+        let message = match saved {
+            Ok(message) => message,
+            Err(err) => {
+                println!(
+                    "Failed to save direct message in {}: {}",
+                    conversation_id, err
+                );
+                self.send_error(session_id, ErrorOutput::ServerError).await;
+                return;
+            }
+        };
+
+        if let Some(nonce) = body.nonce {
+            self.remember_nonce(session_id, nonce, message.id).await;
+        }
+
+        let output = Output::DirectMessage(DirectMessageOutput::new(
+            MessageModelResponse::from(&message),
+            conversation_id,
+            body.user_id,
+        ));
+        self.send_session_id(session_id, output.clone()).await;
+        self.send_session_id(body.user_id, output).await;
+    }
+
+    async fn find_nonce(&self, session_id: Uuid, nonce: &str) -> Option<Uuid> {
+        self.nonces
+            .read()
+            .await
+            .get(&session_id)?
+            .iter()
+            .find(|(seen, _)| seen == nonce)
+            .map(|(_, message_id)| *message_id)
+    }
+
+    async fn remember_nonce(&self, session_id: Uuid, nonce: String, message_id: Uuid) {
+        let mut nonces = self.nonces.write().await;
+        let seen = nonces.entry(session_id).or_default();
+        if seen.len() >= MAX_NONCES_PER_SESSION {
+            seen.pop_front();
+        }
+        seen.push_back((nonce, message_id));
+    }
+
+    // Confirm an already posted message to its author again, a message deleted since only gets an ack
+    async fn resend_message(&self, session_id: Uuid, channel_id: Uuid, message_id: Uuid) {
+        let confirmation = self
+            .store
+            .read()
+            .await
+            .channel_get(channel_id)
+            .and_then(|channel| {
+                let message = MessageModelResponse::from(channel.message_get_by_id(message_id)?);
+                if !channel.is_direct() {
+                    return Some(Output::UserMessage(UserMessageOutput::new(
+                        message,
+                        ChannelModelResponse::from(channel),
+                    )));
+                }
+                let recipient_id = channel.other_participant(session_id)?;
+                Some(Output::DirectMessage(DirectMessageOutput::new(
+                    message,
+                    channel.id,
+                    recipient_id,
+                )))
+            });
+        if let Some(confirmation) = confirmation {
+            self.send_session_id(session_id, confirmation).await;
+        }
+    }
+
+    // Handle the author of a message changing its body
+    async fn process_edit_message(&self, session_id: Uuid, body: EditMessageEvent) {
+        let session = match self.get_session(session_id).await {
+            Some(session) => session,
+            None => return,
+        };
+
+        if !self.is_valid_message_body(&body.body) {
+            self.send_error(session_id, ErrorOutput::InvalidMessageRequest)
+                .await;
+            return;
+        }
+
+        // Mentions are resolved again, only users the edit mentions for the first time are notified
+        let mentions = find_mentions(self.store.read().await.as_ref(), &body.body);
+        let mut newly_mentioned = Vec::new();
+        let new_body = body.body;
+        let result = self
+            .update_message(
+                &session,
+                body.channel_id,
+                body.message_id,
+                |store, mut message, channel| {
+                    newly_mentioned = mentions
+                        .iter()
+                        .copied()
+                        .filter(|user_id| {
+                            *user_id != session_id && !message.mentions.contains(user_id)
+                        })
+                        .collect();
+                    message.body = new_body;
+                    message.edited_at = Some(Utc::now());
+                    message.mentions = mentions;
+                    store.message_save(message.clone())?;
+                    Ok(UserMessageOutput::new(
+                        MessageModelResponse::from(&message),
+                        channel,
+                    ))
+                },
+            )
+            .await;
+
+        let response_packet = match result {
+            Ok(response_packet) => response_packet,
+            Err(error) => {
+                self.send_error(session_id, error).await;
+                return;
+            }
+        };
+        self.send_channel(
+            body.channel_id,
+            Output::MessageEdited(response_packet.clone()),
+        )
+        .await;
+        for user_id in newly_mentioned {
+            self.send_session_id(user_id, Output::Mentioned(response_packet.clone()))
+                .await;
+        }
+    }
+
+    // Handle the author of a message removing it from the channel
+    async fn process_delete_message(&self, session_id: Uuid, body: DeleteMessageEvent) {
+        let session = match self.get_session(session_id).await {
+            Some(session) => session,
+            None => return,
+        };
+
+        // Deleting a reply takes it off its parent's reply count
+        let mut parent = None;
+        let result = self
+            .update_message(
+                &session,
+                body.channel_id,
+                body.message_id,
+                |store, message, channel| {
+                    let thread_parent = message
+                        .parent_id
+                        .and_then(|parent_id| {
+                            store.channel_get(channel.id)?.message_get_by_id(parent_id)
+                        })
+                        .cloned();
+                    store.message_delete(channel.id, message.id)?;
+                    if let Some(mut thread_parent) = thread_parent {
+                        thread_parent.reply_count = thread_parent.reply_count.saturating_sub(1);
+                        store.message_save(thread_parent.clone())?;
+                        parent = Some(thread_parent);
+                    }
+                    Ok(Output::MessageDeleted(MessageDeletedOutput::new(
+                        channel.id, message.id,
+                    )))
+                },
+            )
+            .await;
+
+        match result {
+            Ok(output) => self.send_channel(body.channel_id, output).await,
+            Err(error) => self.send_error(session_id, error).await,
+        }
+        if let Some(parent) = parent {
+            self.send_channel(
+                parent.channel_id,
+                Output::ThreadUpdated(ThreadUpdatedOutput::new(&parent)),
+            )
+            .await;
+        }
+    }
+
+    // Handle a user adding or removing a reaction on a message in one of their channels
+    async fn process_reaction(&self, session_id: Uuid, body: ReactionEvent, add: bool) {
+        let session = match self.get_session(session_id).await {
+            Some(session) => session,
+            None => return,
+        };
+
+        if !session.is_member(body.channel_id) {
+            self.send_error(session_id, ErrorOutput::InvalidChannel)
+                .await;
+            return;
+        }
+        if !is_valid_reaction(&body.emoji) {
+            self.send_error(session_id, ErrorOutput::InvalidMessageRequest)
+                .await;
+            return;
+        }
+
+        let result = {
+            let mut store = self.store.write().await;
+            let message = store
+                .channel_get(body.channel_id)
+                .and_then(|channel| channel.message_get_by_id(body.message_id))
+                .cloned();
+            match message {
+                None => Err(ErrorOutput::MessageNotFound),
+                Some(message)
+                    if add
+                        && !message.reactions.contains_key(&body.emoji)
+                        && message.reactions.len() >= MAX_REACTIONS_PER_MESSAGE =>
+                {
+                    Err(ErrorOutput::InvalidMessageRequest)
+                }
+                Some(mut message) => {
+                    let changed = if add {
+                        message.add_reaction(&body.emoji, session_id)
+                    } else {
+                        message.remove_reaction(&body.emoji, session_id)
+                    };
+                    // Repeating a reaction, or removing one that isn't there, isn't written again
+                    let saved = if changed {
+                        store.message_save(message.clone())
+                    } else {
+                        Ok(())
+                    };
+                    saved.map(|_| (changed, message)).map_err(|err| {
+                        println!("Failed to save reaction on {}: {}", body.message_id, err);
+                        ErrorOutput::ServerError
+                    })
+                }
+            }
+        };
+
+        match result {
+            Ok((changed, message)) => {
+                let output = Output::ReactionUpdated(ReactionUpdatedOutput::new(
+                    &message, session_id, body.emoji,
+                ));
+                // Nothing changed, so only the sender hears back
+                if changed {
+                    self.send_channel(body.channel_id, output).await;
+                } else {
+                    self.send_session_id(session_id, output).await;
+                }
+            }
+            Err(error) => self.send_error(session_id, error).await,
+        }
+    }
+
+    // Apply a change to a message in one of the session's channels, verifying the session authored it
+    async fn update_message<F, T>(
+        &self,
+        session: &Session,
+        channel_id: Uuid,
+        message_id: Uuid,
+        update: F,
+    ) -> Result<T, ErrorOutput>
+    where
+        F: FnOnce(&mut dyn HoloStore, Message, ChannelModelResponse) -> holo_errors::Result<T>,
+    {
+        if !session.is_member(channel_id) {
+            return Err(ErrorOutput::InvalidChannel);
+        }
+
+        let mut store = self.store.write().await;
+        let channel = store
+            .channel_get(channel_id)
+            .ok_or(ErrorOutput::InvalidChannel)?;
+        let message = channel
+            .message_get_by_id(message_id)
+            .cloned()
+            .ok_or(ErrorOutput::MessageNotFound)?;
+        if message.created_by != session.id {
+            return Err(ErrorOutput::Forbidden);
+        }
+
+        let channel = ChannelModelResponse::from(channel);
+        update(store.as_mut(), message, channel).map_err(|err| {
+            println!("Failed to update message {}: {}", message_id, err);
+            ErrorOutput::ServerError
+        })
+    }
+
+    async fn process_keep_alive(&self) {
+        let alive_interval = match self.alive_interval {
+            Some(alive_interval) => alive_interval,
+            None => return futures::future::pending().await,
+        };
+        // Any frame from the client resets its idle time, pongs included
+        let max_idle = alive_interval * self.max_missed_pongs;
+
+        loop {
+            time::sleep(alive_interval).await;
+
+            // Every node keeps its own connections alive, pings are never fanned out
+            // detached is copied out first, handle_disconnect and resume take it while holding outboxes
+            let mut evicted = Vec::new();
+            let detached: HashSet<Uuid> = self.detached.read().await.keys().copied().collect();
+            for (session_id, outbox) in self.outboxes.read().await.iter() {
+                // Nobody is on the other end of a detached session's buffer
+                if detached.contains(session_id) {
+                    continue;
+                }
+                if outbox.idle_for() >= max_idle {
+                    evicted.push((*session_id, outbox.clone()));
+                } else {
+                    outbox.ping();
+                }
+            }
+
+            // Evict through handle_disconnect so a half-open connection stuck on a write still leaves right away
+            // The connection's own disconnect then finds its outbox already replaced and leaves the session alone
+            for (session_id, outbox) in evicted {
+                println!("Evicting idle session {}", session_id);
+                self.handle_disconnect(session_id, &outbox).await;
+            }
+        }
+    }
+
+    /////////////////////
+    //  The following seeries of send() functions handle the logic of directing responses to each session's outbox
+    // In cluster mode they are also published to Redis so other nodes can "echo" them to their own sessions
+    /////////////////////
+    async fn send_session_id(&self, session_id: Uuid, output: Output) {
+        self.send_to(Audience::Session { session_id }, output).await;
+    }
+
+    // Send a message to everyone but the specified session ID
+    async fn send_except_session_id(&self, session_id: Uuid, output: Output) {
+        self.send_to(Audience::AllExcept { session_id }, output)
+            .await;
+    }
+
+    // Send a message to every member of a channel
+    async fn send_channel(&self, channel_id: Uuid, output: Output) {
+        self.send_to(Audience::Channel { channel_id }, output).await;
+    }
+
+    async fn send_system_message(&self, session_id: Uuid, channel_id: Uuid, body: String) {
+        self.send_session_id(
+            session_id,
+            Output::SystemMessage(SystemMessageOutput::new(channel_id, body)),
+        )
+        .await;
+    }
+
+    // Send a message to every member of a channel but the specified session ID
+    async fn send_channel_except_session_id(
+        &self,
+        channel_id: Uuid,
+        session_id: Uuid,
+        output: Output,
+    ) {
+        self.send_to(
+            Audience::ChannelExcept {
+                channel_id,
+                session_id,
+            },
+            output,
+        )
+        .await;
+    }
+
+    async fn send_to(&self, audience: Audience, output: Output) {
+        if let Some(cluster) = &self.cluster {
+            // Replies to one of our own connections never need to leave this node, joined or not
+            let local_session = match audience {
+                Audience::Session { session_id } => {
+                    self.outboxes.read().await.contains_key(&session_id)
+                }
+                _ => false,
+            };
+            if !local_session {
+                cluster.publish(audience, output.clone());
+            }
+        }
+
+        self.deliver(audience, output).await;
+    }
+
+    // Hand an output to every local session in the audience
+    async fn deliver(&self, audience: Audience, output: Output) {
+        let outboxes = self.outboxes.read().await;
+
+        // A connection can be addressed before it has joined, ex: to tell it a name is taken
+        if let Audience::Session { session_id } = audience {
+            if let Some(outbox) = outboxes.get(&session_id) {
+                outbox.push(
+                    ResponsePacket::new(session_id, Uuid::nil(), output)
+                        .with_request_id(reply_request_id(session_id)),
+                );
+            }
+            return;
+        }
+
+        let store = self.store.read().await;
+        store
+            .sessions()
+            .filter(|session| audience.includes(session))
+            .for_each(|session| {
+                if let Some(outbox) = outboxes.get(&session.id) {
+                    outbox.push(
+                        ResponsePacket::new(session.id, audience.channel_id(), output.clone())
+                            .with_request_id(reply_request_id(session.id)),
+                    );
+                }
+            });
+    }
+
+    async fn send_error(&self, session_id: Uuid, error: ErrorOutput) {
+        println!("sending errors!");
+        self.send_session_id(session_id, Output::Error(error)).await;
+    }
+}
+
+// Replies to the sender of the request being handled carry its requestId
+// Outside of handling a request, ex: outputs replayed from the cluster, there is nothing to reply to
+fn reply_request_id(session_id: Uuid) -> Option<String> {
+    CURRENT_REQUEST
+        .try_with(|request| {
+            if request.session_id != session_id {
+                return None;
+            }
+            request.replied.set(true);
+            request.request_id.clone()
+        })
+        .ok()
+        .flatten()
+}
+
+// One grapheme, so skin tones and flags work but sentences don't
+fn is_valid_reaction(emoji: &str) -> bool {
+    !emoji.is_empty()
+        && emoji.len() <= MAX_REACTION_LENGTH
+        && emoji.graphemes(true).count() == 1
+        && !emoji.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+// Sessions named by @name tokens in a message body, in the order they are first mentioned
+// Only names made of word characters, dots and dashes can be mentioned
+fn find_mentions(store: &dyn HoloStore, body: &str) -> Vec<Uuid> {
+    let mut mentions = Vec::new();
+    for captures in MENTION_PATTERN.captures_iter(body) {
+        let name = &captures[1];
+        let found = store.sessions().find(|session| session.has_name(name));
+        if let Some(session) = found {
+            if !mentions.contains(&session.id) {
+                mentions.push(session.id);
+            }
+        }
+    }
+    mentions
+}
+
+// Whether any live session other than `session_id` already goes by this name
+fn is_name_taken(store: &dyn HoloStore, session_id: Uuid, name: &str) -> bool {
+    store
+        .sessions()
+        .any(|session| session.id != session_id && session.has_name(name))
+}
+
+// What a guest is renamed to when an authenticated user claims its name, ex: guest-1a2b3c4d
+fn guest_name(store: &dyn HoloStore, guest_id: Uuid) -> String {
+    let simple_id = guest_id.to_simple().to_string();
+    let guest_name = format!("guest-{}", &simple_id[..8]);
+    if is_name_taken(store, guest_id, &guest_name) {
+        return format!("guest-{}", simple_id);
+    }
+    guest_name
+}
+
+// Save a newly joined session, first renaming the guest that was using its name
+// Returns the renamed guest and the name it had
+fn claim_name(
+    store: &mut dyn HoloStore,
+    session: Session,
+    guest_id: Option<Uuid>,
+) -> holo_errors::Result<Option<(UserModelResponse, String)>> {
+    let renamed = match guest_id.and_then(|guest_id| store.session_get(guest_id).cloned()) {
+        Some(mut guest) => {
+            let guest_name = guest_name(store, guest.id);
+            let previous_name = std::mem::replace(&mut guest.name, guest_name);
+            let user = UserModelResponse::from(&guest);
+            store.session_save(guest)?;
+            Some((user, previous_name))
+        }
+        None => None,
+    };
+    store.session_save(session)?;
+    Ok(renamed)
+}
+
+// Members of a channel, or every session when no channel is given, sorted by name
+fn roster(store: &dyn HoloStore, channel_id: Option<Uuid>) -> Vec<UserModelResponse> {
+    let mut users: Vec<UserModelResponse> = store
+        .sessions()
+        .filter(|session| channel_id.is_none_or(|channel_id| session.is_member(channel_id)))
+        .map(UserModelResponse::from)
+        .collect();
+    users.sort_by(|a, b| a.name.cmp(&b.name));
+    users
+}
+
+fn count_members(store: &dyn HoloStore, channel_id: Uuid) -> usize {
+    store
+        .sessions()
+        .filter(|session| session.is_member(channel_id))
+        .count()
+}
+
+fn is_channel_full(channel: &ChannelModelResponse, member_count: usize) -> bool {
+    channel
+        .max_members
+        .is_some_and(|max_members| member_count >= max_members)
+}
+
+impl Default for Holocaster {
+    fn default() -> Self {
+        Self::new(HolocasterConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::holo::holo_auth::Identity;
+
+    fn holocaster(resume_grace: Option<Duration>) -> Holocaster {
+        Holocaster::new(HolocasterConfig {
+            resume_grace,
+            ..Default::default()
+        })
+    }
+
+    async fn request(holocaster: &Holocaster, session_id: Uuid, input: Value) {
+        request_with_id(holocaster, session_id, None, input).await;
+    }
+
+    // Handle an input the way handle_incoming does
+    async fn request_with_id(
+        holocaster: &Holocaster,
+        session_id: Uuid,
+        request_id: Option<&str>,
+        input: Value,
+    ) {
+        let input: Input = serde_json::from_value(input).unwrap();
+        let request_id = request_id.map(String::from);
+        let context = RequestContext {
+            session_id,
+            request_id: request_id.clone(),
+            replied: Cell::new(false),
+        };
+        let packet = RequestPacket::new(session_id, input).with_request_id(request_id);
+        CURRENT_REQUEST
+            .scope(context, holocaster.handle_request(packet))
+            .await;
+    }
+
+    // Every output queued so far, as sent over the socket
+    fn drain(outbox: &Outbox) -> Vec<Value> {
+        let mut outputs = Vec::new();
+        while let Some(item) = outbox.try_recv() {
+            if let Outgoing::Packet(packet) = item {
+                outputs.push(serde_json::to_value(&packet.output).unwrap());
+            }
+        }
+        outputs
+    }
+
+    fn types(outputs: &[Value]) -> Vec<&str> {
+        outputs
+            .iter()
+            .map(|output| output["type"].as_str().unwrap())
+            .collect()
+    }
+
+    // Connect and join under `name`, returning the session, its outbox and its user-joined payload
+    async fn join(holocaster: &Holocaster, name: &str) -> (Uuid, Arc<Outbox>, Value) {
+        let (session_id, outbox) = holocaster.connect(&HoloClient::new(), None).await;
+        request(
+            holocaster,
+            session_id,
+            json!({"type": "join", "payload": {"userName": name}}),
+        )
+        .await;
+        let joined = drain(&outbox)
+            .into_iter()
+            .find(|output| output["type"] == "user-joined")
+            .expect("no user-joined");
+        (session_id, outbox, joined["payload"].clone())
+    }
+
+    async fn post(holocaster: &Holocaster, session_id: Uuid, body: &str) {
+        let channel_id = DEFAULT_HOLONET_CHANNEL.id;
+        request(
+            holocaster,
+            session_id,
+            json!({"type": "message", "payload": {"channelId": channel_id, "body": body}}),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn resuming_within_the_grace_replays_what_was_missed_in_order() {
+        let holocaster = holocaster(Some(Duration::from_secs(30)));
+        let (alice_id, alice, joined) = join(&holocaster, "alice").await;
+        let (bob_id, bob, _) = join(&holocaster, "bob").await;
+        drain(&alice);
+
+        holocaster.handle_disconnect(alice_id, &alice).await;
+        for body in &["one", "two", "three"] {
+            post(&holocaster, bob_id, body).await;
+        }
+        drain(&bob);
+
+        let resume_token = joined["resumeToken"].as_str().unwrap();
+        let (resumed_id, resumed) = holocaster
+            .connect(&HoloClient::new(), Some(resume_token))
+            .await;
+        assert_eq!(resumed_id, alice_id);
+
+        let outputs = drain(&resumed);
+        assert_eq!(
+            types(&outputs),
+            ["session-resumed", "message", "message", "message"]
+        );
+        let bodies: Vec<&Value> = outputs[1..]
+            .iter()
+            .map(|output| &output["payload"]["message"]["body"])
+            .collect();
+        assert_eq!(bodies, [&json!("one"), &json!("two"), &json!("three")]);
+
+        // Nobody else saw alice go or come back
+        assert!(drain(&bob).is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_replaced_connection_disconnecting_changes_nothing() {
+        let holocaster = holocaster(Some(Duration::from_secs(30)));
+        let (alice_id, alice, joined) = join(&holocaster, "alice").await;
+        let (bob_id, bob, _) = join(&holocaster, "bob").await;
+        drain(&alice);
+
+        // Alice comes back before her old connection was noticed dropping
+        let resume_token = joined["resumeToken"].as_str().unwrap();
+        let (_, resumed) = holocaster
+            .connect(&HoloClient::new(), Some(resume_token))
+            .await;
+        assert!(alice.is_closed());
+        drain(&resumed);
+
+        holocaster.handle_disconnect(alice_id, &alice).await;
+        assert!(holocaster.get_session(alice_id).await.is_some());
+        assert!(!resumed.is_closed());
+
+        post(&holocaster, bob_id, "still there?").await;
+        assert_eq!(types(&drain(&resumed)), ["message"]);
+        assert_eq!(types(&drain(&bob)), ["user-message"]);
+    }
+
+    #[tokio::test]
+    async fn replay_keeps_only_the_newest_outputs_that_fit_the_outbox() {
+        let holocaster = Holocaster::new(HolocasterConfig {
+            resume_grace: Some(Duration::from_secs(30)),
+            outbox_capacity: Some(3),
+            ..Default::default()
+        });
+        let (alice_id, alice, joined) = join(&holocaster, "alice").await;
+        let (bob_id, _bob, _) = join(&holocaster, "bob").await;
+
+        holocaster.handle_disconnect(alice_id, &alice).await;
+        for body in &["one", "two", "three", "four"] {
+            post(&holocaster, bob_id, body).await;
+        }
+
+        let resume_token = joined["resumeToken"].as_str().unwrap();
+        let (_, resumed) = holocaster
+            .connect(&HoloClient::new(), Some(resume_token))
+            .await;
+        let outputs = drain(&resumed);
+        assert_eq!(types(&outputs), ["session-resumed", "message", "message"]);
+        assert_eq!(outputs[1]["payload"]["message"]["body"], "three");
+        assert_eq!(outputs[1]["payload"]["message"]["seq"], 3);
+
+        // The client sees the gap in seqs and fills it from history
+        request(
+            &holocaster,
+            alice_id,
+            json!({"type": "fetch-history", "payload": {
+                "channelId": DEFAULT_HOLONET_CHANNEL.id,
+                "afterSeq": 0,
+                "limit": 2,
+            }}),
+        )
+        .await;
+        let history = drain(&resumed);
+        assert_eq!(types(&history), ["history"]);
+        let bodies: Vec<&Value> = history[0]["payload"]["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|message| &message["body"])
+            .collect();
+        assert_eq!(bodies, [&json!("one"), &json!("two")]);
+    }
+
+    #[tokio::test]
+    async fn without_a_grace_a_dropped_session_ends_for_everyone() {
+        let holocaster = holocaster(None);
+        let (alice_id, alice, _) = join(&holocaster, "alice").await;
+        let (_, bob, _) = join(&holocaster, "bob").await;
+
+        holocaster.handle_disconnect(alice_id, &alice).await;
+        assert!(holocaster.get_session(alice_id).await.is_none());
+        assert_eq!(types(&drain(&bob)), ["user-disconnect"]);
+    }
+
+    #[tokio::test]
+    async fn names_are_claimed_once_however_they_are_written() {
+        let holocaster = holocaster(None);
+        let (alice_id, alice, _) = join(&holocaster, "Zoé").await;
+
+        // "ZOE\u{301}" is the same name, decomposed and in another case
+        let (bob_id, bob) = holocaster.connect(&HoloClient::new(), None).await;
+        for name in &["zoé", "ZOE\u{301}"] {
+            request(
+                &holocaster,
+                bob_id,
+                json!({"type": "join", "payload": {"userName": name}}),
+            )
+            .await;
+            let outputs = drain(&bob);
+            assert_eq!(types(&outputs), ["error"]);
+            assert_eq!(outputs[0]["payload"]["code"], "name-taken");
+        }
+
+        // The client retries with another name
+        request(
+            &holocaster,
+            bob_id,
+            json!({"type": "join", "payload": {"userName": "bob"}}),
+        )
+        .await;
+        assert!(types(&drain(&bob)).contains(&"user-joined"));
+        drain(&alice);
+
+        request(
+            &holocaster,
+            bob_id,
+            json!({"type": "change-name", "payload": {"userName": "ZOÉ"}}),
+        )
+        .await;
+        assert_eq!(drain(&bob)[0]["payload"]["code"], "name-taken");
+
+        request(
+            &holocaster,
+            bob_id,
+            json!({"type": "change-name", "payload": {"userName": "robert"}}),
+        )
+        .await;
+        let renamed = drain(&alice);
+        assert_eq!(types(&renamed), ["user-renamed"]);
+        assert_eq!(renamed[0]["payload"]["user"]["name"], "robert");
+        assert_eq!(renamed[0]["payload"]["previousName"], "bob");
+        assert!(holocaster.get_session(alice_id).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn a_token_name_wins_over_a_guest_using_it() {
+        let holocaster = holocaster(None);
+        let (guest_id, guest, _) = join(&holocaster, "alice").await;
+
+        let identity = Identity {
+            user_id: Uuid::new_v4(),
+            name: String::from("alice"),
+        };
+        let (alice_id, alice) = holocaster
+            .connect(&HoloClient::with_identity(identity), None)
+            .await;
+        request(
+            &holocaster,
+            alice_id,
+            json!({"type": "join", "payload": {"userName": "alice"}}),
+        )
+        .await;
+        assert!(types(&drain(&alice)).contains(&"user-joined"));
+
+        let renamed = drain(&guest);
+        assert_eq!(types(&renamed)[0], "user-renamed");
+        let guest_name = format!("guest-{}", &guest_id.to_simple().to_string()[..8]);
+        assert_eq!(renamed[0]["payload"]["user"]["name"], json!(guest_name));
+        assert_eq!(renamed[0]["payload"]["previousName"], "alice");
+
+        // A second token for the same name can't take it from the first
+        let identity = Identity {
+            user_id: Uuid::new_v4(),
+            name: String::from("alice"),
+        };
+        let (other_id, other) = holocaster
+            .connect(&HoloClient::with_identity(identity), None)
+            .await;
+        request(
+            &holocaster,
+            other_id,
+            json!({"type": "join", "payload": {"userName": "alice"}}),
+        )
+        .await;
+        assert_eq!(drain(&other)[0]["payload"]["code"], "name-taken");
+    }
+
+    async fn join_channel(
+        holocaster: &Holocaster,
+        session_id: Uuid,
+        channel_id: &Value,
+        wait: bool,
+    ) {
+        request(
+            holocaster,
+            session_id,
+            json!({"type": "join-channel", "payload": {"channelId": channel_id, "wait": wait}}),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn full_channels_seat_their_waitlist_in_order() {
+        let holocaster = holocaster(None);
+        let (alice_id, alice, _) = join(&holocaster, "alice").await;
+        let (bob_id, bob, _) = join(&holocaster, "bob").await;
+        let (carol_id, carol, _) = join(&holocaster, "carol").await;
+        let (dave_id, dave, _) = join(&holocaster, "dave").await;
+
+        request(
+            &holocaster,
+            alice_id,
+            json!({"type": "create-channel", "payload": {"name": "table", "maxMembers": 2}}),
+        )
+        .await;
+        let created = drain(&alice)
+            .into_iter()
+            .find(|output| output["type"] == "channel-created")
+            .unwrap();
+        let channel_id = &created["payload"]["channel"]["id"];
+        join_channel(&holocaster, bob_id, channel_id, false).await;
+        assert!(types(&drain(&bob)).contains(&"channel-joined"));
+
+        drain(&carol);
+        drain(&dave);
+
+        join_channel(&holocaster, carol_id, channel_id, false).await;
+        assert_eq!(drain(&carol)[0]["payload"]["code"], "channel-full");
+        join_channel(&holocaster, carol_id, channel_id, true).await;
+        join_channel(&holocaster, dave_id, channel_id, true).await;
+        let waitlisted = drain(&dave);
+        assert_eq!(types(&waitlisted), ["channel-waitlisted"]);
+        assert_eq!(waitlisted[0]["payload"]["position"], 2);
+        assert_eq!(drain(&carol)[0]["payload"]["position"], 1);
+
+        // Leaving frees a seat for the longest waiting session
+        request(
+            &holocaster,
+            bob_id,
+            json!({"type": "leave-channel", "payload": {"channelId": channel_id}}),
+        )
+        .await;
+        assert!(types(&drain(&carol)).contains(&"channel-joined"));
+        assert!(drain(&dave).is_empty());
+
+        // So does a session ending
+        holocaster.handle_disconnect(carol_id, &carol).await;
+        assert!(types(&drain(&dave)).contains(&"channel-joined"));
+        let session = holocaster.get_session(dave_id).await.unwrap();
+        assert!(session.is_member(serde_json::from_value(channel_id.clone()).unwrap()));
+    }
+
+    // Every packet queued so far as its output type and requestId
+    fn drain_replies(outbox: &Outbox) -> Vec<(Value, Option<String>)> {
+        let mut replies = Vec::new();
+        while let Some(item) = outbox.try_recv() {
+            if let Outgoing::Packet(packet) = item {
+                let output = serde_json::to_value(&packet.output).unwrap();
+                replies.push((output, packet.request_id));
+            }
+        }
+        replies
+    }
+
+    #[tokio::test]
+    async fn retried_messages_are_confirmed_again_instead_of_posted_twice() {
+        let holocaster = holocaster(None);
+        let (alice_id, alice, _) = join(&holocaster, "alice").await;
+        let (_, bob, _) = join(&holocaster, "bob").await;
+        drain(&alice);
+        drain(&bob);
+
+        let message = json!({"type": "message", "payload": {
+            "channelId": DEFAULT_HOLONET_CHANNEL.id,
+            "body": "hello",
+            "nonce": "n1",
+        }});
+        request_with_id(&holocaster, alice_id, Some("r1"), message.clone()).await;
+        request_with_id(&holocaster, alice_id, Some("r2"), message).await;
+
+        let replies = drain_replies(&alice);
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0].0["type"], "user-message");
+        assert_eq!(replies[0].1.as_deref(), Some("r1"));
+        assert_eq!(replies[1].1.as_deref(), Some("r2"));
+        assert_eq!(
+            replies[0].0["payload"]["message"]["id"],
+            replies[1].0["payload"]["message"]["id"]
+        );
+        assert_eq!(types(&drain(&bob)), ["message"]);
+        assert_eq!(holocaster.last_seq(DEFAULT_HOLONET_CHANNEL.id).await, 1);
+    }
+
+    #[tokio::test]
+    async fn every_request_is_answered_with_its_request_id() {
+        let holocaster = holocaster(None);
+        let (alice_id, alice, _) = join(&holocaster, "alice").await;
+        drain(&alice);
+
+        // Typing has no reply of its own, so it is acknowledged
+        request_with_id(
+            &holocaster,
+            alice_id,
+            Some("typing"),
+            json!({"type": "typing-start", "payload": {"channelId": DEFAULT_HOLONET_CHANNEL.id}}),
+        )
+        .await;
+        let replies = drain_replies(&alice);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].0["type"], "ack");
+        assert_eq!(replies[0].1.as_deref(), Some("typing"));
+
+        request_with_id(
+            &holocaster,
+            alice_id,
+            Some("nowhere"),
+            json!({"type": "message", "payload": {"channelId": Uuid::new_v4(), "body": "hi"}}),
+        )
+        .await;
+        let replies = drain_replies(&alice);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].0["type"], "error");
+        assert_eq!(replies[0].1.as_deref(), Some("nowhere"));
     }
 }
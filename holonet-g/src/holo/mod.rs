@@ -1,5 +1,10 @@
 
 pub mod holocaster;
 pub mod holo_api;
+pub mod holo_auth;
+pub mod holo_cluster;
 pub mod holo_client; 
-pub mod holo_errors;
\ No newline at end of file
+pub mod holo_commands;
+pub mod holo_errors;
+pub mod holo_outbox;
+pub mod holo_rate_limit;
@@ -4,3 +4,4 @@ extern crate lazy_static;
 pub mod holo;
 pub mod model;
 pub mod server;
+pub mod store;
@@ -1,8 +1,138 @@
-use holonet::server::Server;
+use std::env;
+use std::time::Duration;
+
+use holonet::holo::holo_auth::AuthConfig;
+use holonet::holo::holo_cluster::ClusterConfig;
+use holonet::holo::holo_commands::CommandRegistry;
+use holonet::holo::holo_outbox::SlowConsumerPolicy;
+use holonet::holo::holo_rate_limit::{RateLimit, RateLimitConfig};
+use holonet::holo::holocaster::HolocasterConfig;
+use holonet::server::{Server, ServerConfig};
+use holonet::store::StorageConfig;
 
 #[tokio::main]
 async fn main() {
-  let server = Server::new(8080);
+  // Several nodes on one host need their own ports when clustering locally
+  let port = env::var("HOLONET_PORT")
+    .ok()
+    .and_then(|port| port.parse().ok())
+    .unwrap_or(8080);
+
+  // Persist chat history to a journal file when HOLONET_STORE_PATH is set, otherwise keep it in memory
+  let storage = match env::var_os("HOLONET_STORE_PATH") {
+    Some(path) => StorageConfig::File(path.into()),
+    None => StorageConfig::Memory,
+  };
+
+  // Join a cluster of Holonet nodes when HOLONET_REDIS_URL is set
+  // Message seqs come from a counter in Redis unless HOLONET_REDIS_SHARED_SEQ=false, ex: for servers without Lua scripting
+  let cluster = env::var("HOLONET_REDIS_URL").ok().map(|redis_url| ClusterConfig {
+    redis_url,
+    topic: env::var("HOLONET_REDIS_TOPIC").unwrap_or_else(|_| String::from("holonet")),
+    shared_seq: !matches!(
+      env::var("HOLONET_REDIS_SHARED_SEQ").as_deref(),
+      Ok("false") | Ok("0")
+    ),
+  });
+
+  // Require signed session tokens on the socket when HOLONET_JWT_SECRET is set
+  let auth = env::var("HOLONET_JWT_SECRET")
+    .ok()
+    .map(|secret| AuthConfig { secret });
+
+  // Cap new channels at this many members unless they set their own limit
+  let max_channel_members = env::var("HOLONET_MAX_CHANNEL_MEMBERS")
+    .ok()
+    .and_then(|max_members| max_members.parse().ok());
+
+  // Slow clients lose their oldest queued outputs unless HOLONET_SLOW_CONSUMER=disconnect
+  let outbox_capacity = env::var("HOLONET_OUTBOX_CAPACITY")
+    .ok()
+    .and_then(|capacity| capacity.parse().ok());
+  let slow_consumer_policy = match env::var("HOLONET_SLOW_CONSUMER").as_deref() {
+    Ok("disconnect") => SlowConsumerPolicy::Disconnect,
+    _ => SlowConsumerPolicy::DropOldest,
+  };
+
+  // Connections are pinged every HOLONET_PING_INTERVAL seconds and evicted after missing HOLONET_MAX_MISSED_PONGS in a row
+  let ping_interval = env::var("HOLONET_PING_INTERVAL")
+    .ok()
+    .and_then(|seconds| seconds.parse().ok())
+    .unwrap_or(20);
+  let max_missed_pongs = env::var("HOLONET_MAX_MISSED_PONGS")
+    .ok()
+    .and_then(|missed| missed.parse().ok());
+
+  // Dropped sessions can be resumed for HOLONET_RESUME_GRACE seconds, 0 turns resuming off
+  let resume_grace = env::var("HOLONET_RESUME_GRACE")
+    .ok()
+    .and_then(|seconds| seconds.parse().ok())
+    .unwrap_or(30);
+
+  // Connections are closed after HOLONET_MAX_INVALID_INPUTS malformed frames, 0 never closes them
+  let max_invalid_inputs = env::var("HOLONET_MAX_INVALID_INPUTS")
+    .ok()
+    .and_then(|max| max.parse().ok())
+    .map_or(Some(5), |max| Some(max).filter(|max| *max > 0));
+
+  // Frames over HOLONET_MAX_FRAME_SIZE bytes close the connection, message bodies are capped in bytes and in graphemes
+  let max_frame_size = env::var("HOLONET_MAX_FRAME_SIZE")
+    .ok()
+    .and_then(|bytes| bytes.parse().ok());
+  let max_message_bytes = env::var("HOLONET_MAX_MESSAGE_BYTES")
+    .ok()
+    .and_then(|bytes| bytes.parse().ok());
+  let max_message_graphemes = env::var("HOLONET_MAX_MESSAGE_GRAPHEMES")
+    .ok()
+    .and_then(|graphemes| graphemes.parse().ok());
+
+  // Connections per minute from one address, messages per second and channel joins, name or status changes and channel creations per minute from one session, 0 lifts a limit
+  // Messages per second and joins per minute are also limited across every session from one address
+  // Sessions rate limited more than HOLONET_MAX_RATE_VIOLATIONS times within a minute are disconnected
+  let rate_limit = |name: &str, default: u32| {
+    env::var(name)
+      .ok()
+      .and_then(|burst| burst.parse().ok())
+      .unwrap_or(default)
+  };
+  let rate_limits = RateLimitConfig {
+    connections: Some(RateLimit::per_minute(rate_limit("HOLONET_RATE_CONNECTIONS", 30))),
+    messages: Some(RateLimit::per_second(rate_limit("HOLONET_RATE_MESSAGES", 5))),
+    joins: Some(RateLimit::per_minute(rate_limit("HOLONET_RATE_JOINS", 20))),
+    address_messages: Some(RateLimit::per_second(rate_limit("HOLONET_RATE_ADDRESS_MESSAGES", 20))),
+    address_joins: Some(RateLimit::per_minute(rate_limit("HOLONET_RATE_ADDRESS_JOINS", 60))),
+    profile_updates: Some(RateLimit::per_minute(rate_limit("HOLONET_RATE_PROFILE_UPDATES", 10))),
+    channel_creates: Some(RateLimit::per_minute(rate_limit("HOLONET_RATE_CHANNEL_CREATES", 5))),
+    max_violations: Some(RateLimit::per_minute(rate_limit("HOLONET_MAX_RATE_VIOLATIONS", 10))),
+  };
+
+  // Tell channel members how far everyone else has read when HOLONET_READ_RECEIPTS=true
+  let broadcast_read_receipts = matches!(
+    env::var("HOLONET_READ_RECEIPTS").as_deref(),
+    Ok("true") | Ok("1")
+  );
+
+  let server = Server::new(ServerConfig {
+    port,
+    auth,
+    max_invalid_inputs,
+    max_frame_size,
+    holocaster: HolocasterConfig {
+      alive_interval: Some(Duration::from_secs(ping_interval)),
+      max_missed_pongs,
+      storage,
+      cluster,
+      max_channel_members,
+      outbox_capacity,
+      slow_consumer_policy,
+      resume_grace: Some(Duration::from_secs(resume_grace)).filter(|grace| !grace.is_zero()),
+      max_message_bytes,
+      max_message_graphemes,
+      rate_limits,
+      broadcast_read_receipts,
+      commands: CommandRegistry::default(),
+    },
+  });
 
   server.run().await;
 
@@ -1,24 +1,53 @@
 // use std::ptr;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::model::message::Message;
 
 const MAX_RECENT_MESSAGE_LENGTH: u16 = 100;
 
-// TODO: eventually we will place DB hooks in instead of storing only in-memory
+// Where a page of history starts or ends (exclusive), pages are always returned oldest first
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageCursor {
+    Latest,
+    BeforeId(Uuid),
+    BeforeTime(DateTime<Utc>),
+    BeforeSeq(u64),
+    // The only cursor that pages forward, used to fill gaps
+    AfterSeq(u64),
+}
 
-#[derive(Default, Clone)]
+// Persisting channels is handled by the store, see crate::store::HoloStore
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Channel {
-    pub messages: Vec<Message>,
+    // Messages are persisted individually by the store, keyed and ordered by their sequence number
+    #[serde(skip)]
+    pub messages: BTreeMap<u64, Message>,
+    // Where each message sits in `messages`, kept up to date by message_add and message_delete_by_id
+    #[serde(skip)]
+    pub message_seqs: HashMap<Uuid, u64>,
     pub id: Uuid,
     pub game_id: Uuid,
     pub name: String,
+    // Maximum number of members, no limit when unset
+    #[serde(default)]
+    pub max_members: Option<usize>,
+    // Highest sequence number handed out so far, deleting a message never frees its number
+    #[serde(default)]
+    pub last_seq: u64,
+    // The two users of a direct conversation, empty for regular channels
+    #[serde(default)]
+    pub participants: Vec<Uuid>,
 }
 
 impl Channel {
     pub fn new(override_id: Uuid, channel_name: &str, game_id: Uuid) -> Self {
         // For now we allow the ability to define the UUID instead of getting a randomly assigned one
-        // With great power comes great responsibility... 
+        // With great power comes great responsibility...
         let mut the_uuid = override_id;
         if override_id.is_nil() {
             the_uuid = Uuid::new_v4()
@@ -26,68 +55,325 @@ impl Channel {
         Channel {
             id: the_uuid,
             name: String::from(channel_name),
-            game_id: game_id,
-            messages: Vec::new(),
+            game_id,
+            messages: BTreeMap::new(),
+            message_seqs: HashMap::new(),
+            max_members: None,
+            last_seq: 0,
+            participants: Vec::new(),
         }
     }
 
+    // The conversation between two users, it has the same id whichever of them starts it
+    pub fn direct(user_id: Uuid, other_id: Uuid) -> Self {
+        let mut participants = vec![user_id, other_id];
+        participants.sort();
+        let mut channel = Channel::new(
+            direct_conversation_id(user_id, other_id),
+            "direct",
+            Uuid::nil(),
+        );
+        channel.participants = participants;
+        channel
+    }
+
+    pub fn is_direct(&self) -> bool {
+        !self.participants.is_empty()
+    }
+
+    pub fn has_participant(&self, user_id: Uuid) -> bool {
+        self.participants.contains(&user_id)
+    }
+
+    // Who a participant of a direct conversation is talking to
+    pub fn other_participant(&self, user_id: Uuid) -> Option<Uuid> {
+        self.participants
+            .iter()
+            .copied()
+            .find(|participant| *participant != user_id)
+    }
+
+    pub fn with_max_members(mut self, max_members: Option<usize>) -> Self {
+        self.max_members = max_members;
+        self
+    }
+
     pub fn messages_iter(&self) -> impl Iterator<Item = &Message> {
-        self.messages.iter()
+        self.messages.values()
     }
 
-    // Get back a slice of the messages
-    pub fn get_messages_paginate(&self, pos: usize, limit: usize) -> Vec<Message> {
-        let mut nl = limit;
-        if nl > MAX_RECENT_MESSAGE_LENGTH.into() {
-            nl = MAX_RECENT_MESSAGE_LENGTH.into();
-        }
+    // Sequence number for the next message posted to this channel
+    pub fn next_seq(&self) -> u64 {
+        self.last_seq + 1
+    }
+
+    // Get the page of up to `limit` messages next to the cursor
+    // Returns None when the cursor references an unknown message, otherwise the page and whether more messages lie past it
+    pub fn get_messages_page(
+        &self,
+        cursor: MessageCursor,
+        limit: usize,
+    ) -> Option<(Vec<Message>, bool)> {
+        let limit = limit.min(MAX_RECENT_MESSAGE_LENGTH.into());
 
-        let total_messages = self.messages.len();
-        if nl > total_messages {
-            nl = total_messages;
+        if let MessageCursor::AfterSeq(seq) = cursor {
+            let mut newer = self
+                .messages
+                .range(seq.saturating_add(1)..)
+                .map(|(_, message)| message);
+            let page: Vec<Message> = newer.by_ref().take(limit).cloned().collect();
+            return Some((page, newer.next().is_some()));
         }
 
-        return self.messages[pos..nl].to_vec();
+        let end = match cursor {
+            MessageCursor::BeforeId(message_id) => self.message_get_by_id(message_id)?.seq,
+            MessageCursor::BeforeSeq(seq) => seq,
+            _ => self.next_seq(),
+        };
+        let mut older = self
+            .messages
+            .range(..end)
+            .rev()
+            .map(|(_, message)| message)
+            .filter(|message| match cursor {
+                MessageCursor::BeforeTime(before) => message.created_at < before,
+                _ => true,
+            });
+        let mut page: Vec<Message> = older.by_ref().take(limit).cloned().collect();
+        page.reverse();
+
+        Some((page, older.next().is_some()))
     }
 
-    // Assumes messages are already sorted by created date...
-    pub fn get_recent_messages(&self) -> Vec<Message> {
-        let mut total = 100;
-        let total_messages = self.messages.len();
-        if total_messages <= 0 {
-            return self.messages.clone();
-        } else {
-            if total > total_messages {
-                total = total_messages - 1;
+    // Append a message, or replace the one with the same id where it already is
+    // Messages without a sequence number (ex: journaled before we had them) get the next one
+    pub fn message_add(&mut self, mut message: Message) {
+        if let Some(&seq) = self.message_seqs.get(&message.id) {
+            message.seq = seq;
+        } else if message.seq == 0 {
+            message.seq = self.next_seq();
+        }
+        self.last_seq = self.last_seq.max(message.seq);
+        self.message_seqs.insert(message.id, message.seq);
+        let message_id = message.id;
+        if let Some(displaced) = self.messages.insert(message.seq, message) {
+            if displaced.id != message_id {
+                self.message_seqs.remove(&displaced.id);
             }
-            return self.messages[0..total].to_vec();
         }
     }
 
-    pub fn message_add(&mut self, message: Message) {
-        self.messages.push(message);
-        // hopefully we only ever sort after we add a new message!
-        return self.message_sort_by_key();
+    // The message a reply to `message_id` belongs under, replying to a reply joins its thread
+    pub fn thread_root(&self, message_id: Uuid) -> Option<&Message> {
+        let message = self.message_get_by_id(message_id)?;
+        match message.parent_id {
+            Some(root_id) => self.message_get_by_id(root_id),
+            None => Some(message),
+        }
+    }
+
+    // Up to `limit` replies to `parent_id` from before `before_seq`, oldest first, and whether older replies remain
+    pub fn get_thread_page(
+        &self,
+        parent_id: Uuid,
+        before_seq: Option<u64>,
+        limit: usize,
+    ) -> (Vec<Message>, bool) {
+        let limit = limit.min(MAX_RECENT_MESSAGE_LENGTH.into());
+        let end = before_seq.unwrap_or_else(|| self.next_seq());
+        let mut older = self
+            .messages
+            .range(..end)
+            .rev()
+            .map(|(_, message)| message)
+            .filter(|message| message.parent_id == Some(parent_id));
+        let mut page: Vec<Message> = older.by_ref().take(limit).cloned().collect();
+        page.reverse();
+
+        (page, older.next().is_some())
     }
 
-    // TODO: Do we need this to be public? We should only need this when we add an ew message (see add_message)
-    pub fn message_sort_by_key(&mut self) {
-        return self.messages.sort_by_key(|message| message.created_at);
+    // Messages posted after `seq` by anyone but `user_id`
+    pub fn count_unread(&self, seq: u64, user_id: Uuid) -> usize {
+        self.messages
+            .range((Bound::Excluded(seq), Bound::Unbounded))
+            .filter(|(_, message)| message.created_by != user_id)
+            .count()
     }
 
     // Get a Message by UUID (returns a reference, not an index)
-    pub fn message_get_by_id(&mut self, messageId: Uuid) -> &Message {
-        return self
-            .messages
+    pub fn message_get_by_id(&self, message_id: Uuid) -> Option<&Message> {
+        self.messages.get(self.message_seqs.get(&message_id)?)
+    }
+
+    // Remove a message, handing back what was removed
+    pub fn message_delete_by_id(&mut self, message_id: Uuid) -> Option<Message> {
+        let seq = self.message_seqs.remove(&message_id)?;
+        self.messages.remove(&seq)
+    }
+}
+
+// Derived from both user ids, so either side finds the same conversation without looking it up
+pub fn direct_conversation_id(user_id: Uuid, other_id: Uuid) -> Uuid {
+    let (low, high) = if user_id < other_id {
+        (user_id, other_id)
+    } else {
+        (other_id, user_id)
+    };
+    let name = format!("holonet:direct:{}:{}", low, high);
+    Uuid::new_v5(&Uuid::NAMESPACE_OID, name.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::session::Session;
+
+    fn post(channel: &mut Channel, body: &str) -> Uuid {
+        let message = Message::new(
+            Uuid::new_v4(),
+            channel.id,
+            Session::new(Uuid::new_v4(), "luke"),
+            body,
+            Utc::now(),
+        );
+        let message_id = message.id;
+        channel.message_add(message);
+        message_id
+    }
+
+    fn reply(channel: &mut Channel, parent_id: Uuid, body: &str) -> Uuid {
+        let message = Message::new(
+            Uuid::new_v4(),
+            channel.id,
+            Session::new(Uuid::new_v4(), "leia"),
+            body,
+            Utc::now(),
+        )
+        .with_parent_id(Some(parent_id));
+        let message_id = message.id;
+        channel.message_add(message);
+        message_id
+    }
+
+    fn bodies(messages: &[Message]) -> Vec<&str> {
+        messages
             .iter()
-            .find(|&message| message.id == messageId)
+            .map(|message| message.body.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn messages_page_of_empty_channel() {
+        let channel = Channel::new(Uuid::nil(), "empty", Uuid::nil());
+        let (page, has_more) = channel
+            .get_messages_page(MessageCursor::Latest, 10)
+            .unwrap();
+        assert!(page.is_empty());
+        assert!(!has_more);
+
+        let (page, has_more) = channel
+            .get_messages_page(MessageCursor::AfterSeq(0), 10)
+            .unwrap();
+        assert!(page.is_empty());
+        assert!(!has_more);
+    }
+
+    #[test]
+    fn messages_page_with_limit_past_the_count() {
+        let mut channel = Channel::new(Uuid::nil(), "general", Uuid::nil());
+        for body in &["one", "two", "three"] {
+            post(&mut channel, body);
+        }
+
+        let (page, has_more) = channel
+            .get_messages_page(MessageCursor::Latest, 10)
+            .unwrap();
+        assert_eq!(bodies(&page), vec!["one", "two", "three"]);
+        assert!(!has_more);
+
+        let (page, has_more) = channel.get_messages_page(MessageCursor::Latest, 2).unwrap();
+        assert_eq!(bodies(&page), vec!["two", "three"]);
+        assert!(has_more);
+
+        let (page, has_more) = channel
+            .get_messages_page(MessageCursor::AfterSeq(1), 10)
+            .unwrap();
+        assert_eq!(bodies(&page), vec!["two", "three"]);
+        assert!(!has_more);
+    }
+
+    #[test]
+    fn messages_page_before_deleted_message() {
+        let mut channel = Channel::new(Uuid::nil(), "general", Uuid::nil());
+        post(&mut channel, "one");
+        let deleted_id = post(&mut channel, "two");
+        post(&mut channel, "three");
+        let deleted_seq = channel.message_get_by_id(deleted_id).unwrap().seq;
+        assert!(channel.message_delete_by_id(deleted_id).is_some());
+
+        // The id is gone, its seq still works as a cursor
+        assert!(channel
+            .get_messages_page(MessageCursor::BeforeId(deleted_id), 10)
+            .is_none());
+        let (page, has_more) = channel
+            .get_messages_page(MessageCursor::BeforeSeq(deleted_seq), 10)
             .unwrap();
+        assert_eq!(bodies(&page), vec!["one"]);
+        assert!(!has_more);
+
+        // Deleting never frees a seq
+        assert_eq!(channel.next_seq(), 4);
+    }
+
+    #[test]
+    fn thread_page_of_message_without_replies() {
+        let mut channel = Channel::new(Uuid::nil(), "general", Uuid::nil());
+        let parent_id = post(&mut channel, "parent");
+        post(&mut channel, "unrelated");
+
+        let (page, has_more) = channel.get_thread_page(parent_id, None, 10);
+        assert!(page.is_empty());
+        assert!(!has_more);
+
+        let empty = Channel::new(Uuid::nil(), "empty", Uuid::nil());
+        let (page, has_more) = empty.get_thread_page(parent_id, None, 10);
+        assert!(page.is_empty());
+        assert!(!has_more);
+    }
+
+    #[test]
+    fn thread_page_with_limit_past_the_count() {
+        let mut channel = Channel::new(Uuid::nil(), "general", Uuid::nil());
+        let parent_id = post(&mut channel, "parent");
+        reply(&mut channel, parent_id, "first");
+        post(&mut channel, "unrelated");
+        reply(&mut channel, parent_id, "second");
+
+        let (page, has_more) = channel.get_thread_page(parent_id, None, 10);
+        assert_eq!(bodies(&page), vec!["first", "second"]);
+        assert!(!has_more);
+
+        let (page, has_more) = channel.get_thread_page(parent_id, None, 1);
+        assert_eq!(bodies(&page), vec!["second"]);
+        assert!(has_more);
     }
 
-    // Edit a message
-    pub fn message_edit_by_id(&mut self, messageId: Uuid) -> &Message {
-        let message = self.message_get_by_id(messageId);
-        // TODO: implement the edit
-        return message;
+    #[test]
+    fn thread_page_before_deleted_reply() {
+        let mut channel = Channel::new(Uuid::nil(), "general", Uuid::nil());
+        let parent_id = post(&mut channel, "parent");
+        reply(&mut channel, parent_id, "first");
+        let deleted_id = reply(&mut channel, parent_id, "second");
+        reply(&mut channel, parent_id, "third");
+        let deleted_seq = channel.message_get_by_id(deleted_id).unwrap().seq;
+        channel.message_delete_by_id(deleted_id);
+
+        let (page, has_more) = channel.get_thread_page(parent_id, Some(deleted_seq), 10);
+        assert_eq!(bodies(&page), vec!["first"]);
+        assert!(!has_more);
+
+        let (page, _) = channel.get_thread_page(parent_id, None, 10);
+        assert_eq!(bodies(&page), vec!["first", "third"]);
     }
 }
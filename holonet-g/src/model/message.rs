@@ -1,18 +1,39 @@
+use std::collections::{BTreeMap, BTreeSet};
+
 use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::model::session::Session;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub id: Uuid,
     pub channel_id: Uuid,
+    // Position in the channel, assigned by the channel when the message is posted
+    #[serde(default)]
+    pub seq: u64,
     pub body: String,
     // TODO: the holonet-user object is just for quick-lookups... we don't want to store this in the DB< just the user UUID
+    #[serde(skip)]
     pub user: Session,
     // This is the Holonet-Session UUID
     pub created_by: Uuid,
     pub created_at: DateTime<Utc>,
+    // Only set once the author has edited the message
+    pub edited_at: Option<DateTime<Utc>>,
+    // Who reacted with which emoji, emoji nobody reacts with anymore are dropped
+    #[serde(default)]
+    pub reactions: BTreeMap<String, BTreeSet<Uuid>>,
+    // The message this one replies to, threads are only ever one level deep
+    #[serde(default)]
+    pub parent_id: Option<Uuid>,
+    // How many replies this message has, kept up to date as replies are posted and deleted
+    #[serde(default)]
+    pub reply_count: u64,
+    // Users mentioned by @name in the body, resolved when the message was posted
+    #[serde(default)]
+    pub mentions: Vec<Uuid>,
 }
 
 impl Message {
@@ -26,10 +47,52 @@ impl Message {
         Message {
             id,
             channel_id,
+            seq: 0,
             created_by: user.id,
             user,
             body: String::from(body),
             created_at,
+            edited_at: None,
+            reactions: BTreeMap::new(),
+            parent_id: None,
+            reply_count: 0,
+            mentions: Vec::new(),
+        }
+    }
+
+    pub fn with_seq(mut self, seq: u64) -> Self {
+        self.seq = seq;
+        self
+    }
+
+    pub fn with_parent_id(mut self, parent_id: Option<Uuid>) -> Self {
+        self.parent_id = parent_id;
+        self
+    }
+
+    pub fn with_mentions(mut self, mentions: Vec<Uuid>) -> Self {
+        self.mentions = mentions;
+        self
+    }
+
+    // Returns false if the user already reacted with this emoji
+    pub fn add_reaction(&mut self, emoji: &str, user_id: Uuid) -> bool {
+        self.reactions
+            .entry(String::from(emoji))
+            .or_default()
+            .insert(user_id)
+    }
+
+    // Returns false if the user hadn't reacted with this emoji
+    pub fn remove_reaction(&mut self, emoji: &str, user_id: Uuid) -> bool {
+        let users = match self.reactions.get_mut(emoji) {
+            Some(users) => users,
+            None => return false,
+        };
+        let removed = users.remove(&user_id);
+        if users.is_empty() {
+            self.reactions.remove(emoji);
         }
+        removed
     }
 }
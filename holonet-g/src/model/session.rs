@@ -1,9 +1,32 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
 use uuid::Uuid;
 
-#[derive(Debug, Clone)]
+const MAX_USER_NAME_LENGTH: usize = 32;
+
+// What a user tells everyone else they are up to, sessions start out online
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Presence {
+    #[default]
+    Online,
+    Away,
+    InGame,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Session {
     pub id: Uuid,
     pub name: String,
+    // The channels this session is currently a member of
+    pub channels: Vec<Uuid>,
+    #[serde(default)]
+    pub status: Presence,
+    // Sequence number of the last message read in each channel
+    #[serde(default)]
+    pub read_cursors: HashMap<Uuid, u64>,
 }
 
 impl Session {
@@ -11,6 +34,61 @@ impl Session {
         Session {
             id,
             name: String::from(name),
+            channels: Vec::new(),
+            status: Presence::Online,
+            read_cursors: HashMap::new(),
+        }
+    }
+
+    // True when both names would look the same to other users
+    pub fn has_name(&self, name: &str) -> bool {
+        normalize_name(&self.name) == normalize_name(name)
+    }
+
+    pub fn is_member(&self, channel_id: Uuid) -> bool {
+        self.channels.contains(&channel_id)
+    }
+
+    // Returns false if the session was already a member of the channel
+    // The read cursor starts at `read_seq`, usually the channel's last seq, so older history isn't unread
+    pub fn join_channel(&mut self, channel_id: Uuid, read_seq: u64) -> bool {
+        if self.is_member(channel_id) {
+            return false;
         }
+        self.channels.push(channel_id);
+        self.read_cursors.entry(channel_id).or_insert(read_seq);
+        true
     }
+
+    // Everything up to and including `seq` has been read, 0 when nothing has
+    pub fn read_seq(&self, channel_id: Uuid) -> u64 {
+        self.read_cursors.get(&channel_id).copied().unwrap_or(0)
+    }
+
+    // Returns false if the cursor was already at or past `seq`, read cursors never move back
+    pub fn mark_read(&mut self, channel_id: Uuid, seq: u64) -> bool {
+        if seq <= self.read_seq(channel_id) {
+            return false;
+        }
+        self.read_cursors.insert(channel_id, seq);
+        true
+    }
+
+    // Returns false if the session was not a member of the channel
+    pub fn leave_channel(&mut self, channel_id: Uuid) -> bool {
+        let total_channels = self.channels.len();
+        self.channels.retain(|id| *id != channel_id);
+        self.read_cursors.remove(&channel_id);
+        self.channels.len() != total_channels
+    }
+}
+
+// Display names are checked after trimming, so a name of only spaces is empty
+pub fn is_valid_user_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().count() <= MAX_USER_NAME_LENGTH
+}
+
+// Names are compared case-insensitively after NFKC normalization, so "Luke", "LUKE" and "Ｌｕｋｅ" are the same name
+pub fn normalize_name(name: &str) -> String {
+    name.trim().nfkc().collect::<String>().to_lowercase()
 }
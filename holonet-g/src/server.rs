@@ -1,43 +1,87 @@
+use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::Duration;
-use uuid::Uuid;
 
-use futures::{StreamExt, TryStreamExt};
+use futures::StreamExt;
+use serde::Deserialize;
 // use log::{error, info};
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::UnboundedSender;
-use tokio_stream::wrappers::UnboundedReceiverStream;
 // use tokio_stream::{StreamExt};
 
+use warp::http::StatusCode;
 use warp::ws::WebSocket;
 use warp::Filter;
 
 use crate::holo::holo_api::RequestPacket;
+use crate::holo::holo_auth::{self, AuthConfig, Identity};
 use crate::holo::holo_client::HoloClient;
+use crate::holo::holo_errors::Error;
+use crate::holo::holo_outbox::{CLOSE_MESSAGE_TOO_BIG, CLOSE_POLICY_VIOLATION};
 // use crate::holo::holo_errors::{HoloError, Result};
 use crate::holo::holocaster::{Holocaster, HolocasterConfig};
 
-const MAX_FRAME_SIZE: usize = 1 * 65535;
+// Default for ServerConfig.max_frame_size
+const MAX_FRAME_SIZE: usize = 65535;
+// Offered alongside the token in Sec-WebSocket-Protocol by browser clients
+const HOLONET_PROTOCOL: &str = "holonet";
+
+#[derive(Clone)]
+pub struct ServerConfig {
+  pub port: u16,
+  // When set, every /socket upgrade must carry a valid session token
+  pub auth: Option<AuthConfig>,
+  // How many malformed frames a connection may send before it is closed, None never closes it
+  pub max_invalid_inputs: Option<u32>,
+  // Largest frame, and message, a client may send, bigger ones close the connection before they are read in full
+  pub max_frame_size: Option<usize>,
+  pub holocaster: HolocasterConfig,
+}
+
+#[derive(Debug, Deserialize)]
+struct SocketQuery {
+  token: Option<String>,
+  // Resume token from a previous user-joined or session-resumed
+  resume: Option<String>,
+}
+
+// Everything the client tells us about itself during the handshake
+struct Handshake {
+  query: SocketQuery,
+  protocols: Option<String>,
+  remote: Option<SocketAddr>,
+}
+
+// Per connection limits, handed to every socket upgrade
+#[derive(Debug, Clone, Copy)]
+struct ConnectionLimits {
+  max_invalid_inputs: Option<u32>,
+  max_frame_size: usize,
+}
 
 pub struct Server {
   port: u16,
+  auth: Option<AuthConfig>,
+  limits: ConnectionLimits,
   holocaster: Arc<Holocaster>,
 }
 
 impl Server {
-  pub fn new(port: u16) -> Self {
+  pub fn new(config: ServerConfig) -> Self {
     Server {
-      port,
-      holocaster: Arc::new(Holocaster::new(HolocasterConfig {
-        alive_interval: Some(Duration::from_secs(20)),
-      })),
+      port: config.port,
+      auth: config.auth,
+      limits: ConnectionLimits {
+        max_invalid_inputs: config.max_invalid_inputs,
+        max_frame_size: config.max_frame_size.unwrap_or(MAX_FRAME_SIZE),
+      },
+      holocaster: Arc::new(Holocaster::new(config.holocaster)),
     }
   }
 
   // Boot the server
   pub async fn run(&self) {
     println!("HOLONET BOOT SEQUENCE BEGIN");
-    println!("MAX FRAME SIZE: {:?}", MAX_FRAME_SIZE);
+    println!("MAX FRAME SIZE: {:?}", self.limits.max_frame_size);
 
     // This has shared ownership with Holoc`aster since it is an Arc<T>
     // Meaning that
@@ -50,7 +94,12 @@ impl Server {
     };
 
     // Construct routes, and init the server
-    let routes = Self::build_routes(holocaster, input_sender);
+    let routes = Self::build_routes(
+      holocaster,
+      input_sender,
+      self.auth.clone(),
+      self.limits,
+    );
     let (_, server) = warp::serve(routes)
       .bind_with_graceful_shutdown(([127, 0, 0, 1], self.port), shutdown_handler);
 
@@ -70,6 +119,8 @@ impl Server {
   fn build_routes(
     holocaster: Arc<Holocaster>,
     input_sender: UnboundedSender<RequestPacket>,
+    auth: Option<AuthConfig>,
+    limits: ConnectionLimits,
   ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     println!("Booting routes!!");
     let health = warp::path("healh").map(|| "Fear is the path to the dark side. Fear leads to anger; anger leads to hate; hate leads to suffering. I sense much fear in you.");
@@ -78,66 +129,192 @@ impl Server {
     let socket = warp::path("socket")
       // prepares the websocket handshake
       .and(warp::ws())
-      // Make the input-stream and shared-holocaster Warp-Filters...
+      // A session token can come from either ?token= or the Sec-WebSocket-Protocol header
+      // Connections are rate limited per remote address
+      .and(
+        warp::query::<SocketQuery>()
+          .and(warp::header::optional::<String>("sec-websocket-protocol"))
+          .and(warp::addr::remote())
+          .map(|query, protocols, remote| Handshake { query, protocols, remote }),
+      )
+      // Make the auth-config, input-stream and shared-holocaster Warp-Filters...
+      .and(warp::any().map(move || auth.clone()))
       .and(warp::any().map(move || input_sender.clone()))
       .and(warp::any().map(move || holocaster.clone()))
-      .map(
-        move |ws: warp::ws::Ws,
-              input_sender: UnboundedSender<RequestPacket>,
-              holocaster: Arc<Holocaster>| {
-          ws.on_upgrade(move |web_socket| async move {
-            println!("Attempting to connect!");
-            tokio::spawn(Self::establish_connection(
-              holocaster,
-              web_socket,
-              input_sender,
-            ));
-          })
-        },
-      );
+      .and(warp::any().map(move || limits))
+      .and_then(Self::upgrade);
 
-    let routes = warp::get().and(health.or(socket));
+    warp::get().and(health.or(socket))
+  }
 
-    return routes;
+  // Authenticate the handshake (when auth is configured) and hand the socket off to a new HoloClient
+  async fn upgrade(
+    ws: warp::ws::Ws,
+    handshake: Handshake,
+    auth: Option<AuthConfig>,
+    input_sender: UnboundedSender<RequestPacket>,
+    holocaster: Arc<Holocaster>,
+    limits: ConnectionLimits,
+  ) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let Handshake { query, protocols, remote } = handshake;
+    if let Some(remote) = remote {
+      if let Err(retry_after) = holocaster.admit_connection(remote.ip()) {
+        println!("Too many connections from {}, refusing the upgrade", remote.ip());
+        // Retry-After is in whole seconds, rounded up so an early retry isn't refused again
+        let retry_after = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        return Ok(Box::new(warp::reply::with_header(
+          warp::reply::with_status("Too many connections", StatusCode::TOO_MANY_REQUESTS),
+          "retry-after",
+          retry_after.to_string(),
+        )));
+      }
+    }
+
+    let resume_token = query.resume;
+    let (client, protocol) = match auth {
+      None => (HoloClient::new(), None),
+      Some(auth) => match Self::authenticate(&auth, query.token, protocols) {
+        // The session id comes from the token, a second connection for the same user takes over from the first
+        Some((identity, protocol)) => (HoloClient::with_identity(identity), protocol),
+        None => {
+          return Ok(Box::new(warp::reply::with_status(
+            "Unauthorized",
+            StatusCode::UNAUTHORIZED,
+          )))
+        }
+      },
+    };
+    // Messages and joins are rate limited per remote address as well as per session
+    let client = client.with_remote(remote.map(|remote| remote.ip()));
+
+    // tungstenite refuses oversized frames and messages as they come in, so they are never buffered in full
+    let ws = ws
+      .max_frame_size(limits.max_frame_size)
+      .max_message_size(limits.max_frame_size);
+    let reply = ws.on_upgrade(move |web_socket| async move {
+      println!("Attempting to connect!");
+      tokio::spawn(Self::establish_connection(
+        holocaster,
+        web_socket,
+        input_sender,
+        client,
+        resume_token,
+        limits.max_invalid_inputs,
+      ));
+    });
+
+    // Browsers drop the connection unless we accept one of the protocols they offered
+    match protocol {
+      Some(protocol) => Ok(Box::new(warp::reply::with_header(
+        reply,
+        "sec-websocket-protocol",
+        protocol,
+      ))),
+      None => Ok(Box::new(reply)),
+    }
+  }
+
+  // Returns the identity and, for header tokens, which Sec-WebSocket-Protocol to accept
+  fn authenticate(
+    auth: &AuthConfig,
+    token: Option<String>,
+    protocols: Option<String>,
+  ) -> Option<(Identity, Option<String>)> {
+    if let Some(token) = token {
+      return match holo_auth::authenticate(auth, &token) {
+        Ok(identity) => Some((identity, None)),
+        Err(err) => {
+          println!("Rejecting socket upgrade: {}", err);
+          None
+        }
+      };
+    }
+
+    // Browsers can't set headers on a websocket, so they send `holonet, <token>` as protocols instead
+    let protocols = protocols?;
+    let offered: Vec<&str> = protocols.split(',').map(str::trim).collect();
+    offered
+      .iter()
+      .filter(|protocol| **protocol != HOLONET_PROTOCOL)
+      .find_map(|token| match holo_auth::authenticate(auth, token) {
+        Ok(identity) => Some((identity, *token)),
+        Err(err) => {
+          println!("Rejecting socket upgrade: {}", err);
+          None
+        }
+      })
+      // Exactly one of the offered protocols is echoed back, the token itself when holonet wasn't offered
+      .map(|(identity, token)| {
+        let protocol = if offered.contains(&HOLONET_PROTOCOL) {
+          HOLONET_PROTOCOL
+        } else {
+          token
+        };
+        (identity, Some(String::from(protocol)))
+      })
   }
 
   async fn establish_connection(
     holocaster: Arc<Holocaster>,
     web_socket: WebSocket,
     input_sender: UnboundedSender<RequestPacket>,
+    mut client: HoloClient,
+    resume_token: Option<String>,
+    max_invalid_inputs: Option<u32>,
   ) {
     println!("Establishing a new connection!");
-    // Generate  a new client
-    let default_channels: Vec<Uuid> = Vec::new();
-    let holocaster_listener = holocaster.subscribe();
+
+    // Outputs for this session are routed straight into its outbox, a resumed connection takes over its old session id
+    let (session_id, outbox) = holocaster.connect(&client, resume_token.as_deref()).await;
+    client.id = session_id;
 
     // Socket is  split into a reciever/sender of messages
     let (ws_sink, ws_stream) = web_socket.split();
-    let client = HoloClient::new(default_channels);
 
     // info!("HoloClient {} connected!", client.id);
 
-    // Generate an unbound channel to  handle buffering and flushing of the socket to the Holocaster
-    let (tx, rx) = mpsc::unbounded_channel();
-    let rx = UnboundedReceiverStream::new(rx);
-    tokio::spawn(rx.forward(ws_sink));
-
     // HANDLE INPUT STREAM
     // reading will return back a stream 
-    let reading = client
-      .handle_incoming(ws_stream)
-      .try_for_each(|request_packet| async {
-        println!("Attempting to read message packet!!");
-        input_sender.send(request_packet).unwrap();
-        Ok(())
-      });
+    // Malformed frames are answered with invalid-input, a client that keeps sending them is cut off
+    let reading = async {
+      let mut strikes = 0;
+      let mut incoming = Box::pin(client.handle_incoming(ws_stream, outbox.clone()));
+      while let Some(result) = incoming.next().await {
+        match result {
+          Ok(request_packet) => {
+            println!("Attempting to read message packet!!");
+            input_sender.send(request_packet).unwrap();
+          }
+          Err(Error::InvalidInput(detail)) => {
+            strikes += 1;
+            holocaster.reject_input(client.id, detail).await;
+            if max_invalid_inputs.is_some_and(|max| strikes >= max) {
+              // The writer flushes the error and closes the socket, the session goes with it so it can't be resumed
+              holocaster
+                .disconnect(client.id, &outbox, CLOSE_POLICY_VIOLATION, "too many invalid inputs")
+                .await;
+              return futures::future::pending().await;
+            }
+          }
+          Err(Error::FrameTooLarge) => {
+            // Like too many invalid inputs, the session can't be resumed afterwards
+            holocaster
+              .disconnect(client.id, &outbox, CLOSE_MESSAGE_TOO_BIG, "message too big")
+              .await;
+            return futures::future::pending().await;
+          }
+          Err(err) => return Err(err),
+        }
+      }
+      Ok(())
+    };
 
     if let Err(err) = tokio::select! {
         result = reading => result,
         // HANDLE OUTPUT STREAM
         // 
         _message = client
-        .write_output(holocaster_listener, tx) => {
+        .write_output(&outbox, ws_sink) => {
           println!("ALL MESSAGES SENT CLOSING OUT!");
           Ok(())
         },
@@ -147,7 +324,7 @@ impl Server {
       println!("HoloClient {} disconnected", client.id);
     }
 
-    holocaster.handle_disconnect(client.id).await;
+    holocaster.handle_disconnect(client.id, &outbox).await;
     println!("!!!! The HoloClient {} DISCONNECTED with client ID:: ", client.id);
   }
 }
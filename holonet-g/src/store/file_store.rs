@@ -0,0 +1,314 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::holo::holo_errors::{Error, Result};
+use crate::model::channel::Channel;
+use crate::model::message::Message;
+use crate::model::session::Session;
+use crate::store::memory_store::MemoryStore;
+use crate::store::HoloStore;
+
+// One line of the journal, every change to the store is appended as one of these
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload")]
+enum JournalEntry {
+    #[serde(rename = "channel-saved")]
+    ChannelSaved(Channel),
+    #[serde(rename = "message-saved")]
+    MessageSaved(Message),
+    #[serde(rename = "message-deleted")]
+    MessageDeleted { channel_id: Uuid, message_id: Uuid },
+}
+
+// Keeps everything in memory and appends every change to a JSON-lines journal
+// On open the journal is replayed and then compacted down to the current state
+// Sessions are never journaled, none of them outlive a restart
+pub struct FileStore {
+    journal: File,
+    inner: MemoryStore,
+}
+
+impl FileStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut inner = MemoryStore::default();
+        if path.exists() {
+            Self::replay(path, &mut inner)?;
+        }
+        Self::compact(path, &inner)?;
+
+        let journal = OpenOptions::new().append(true).create(true).open(path)?;
+        Ok(FileStore { journal, inner })
+    }
+
+    fn replay(path: &Path, inner: &mut MemoryStore) -> Result<()> {
+        let reader = BufReader::new(File::open(path)?);
+        for (line_number, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            // A crash mid-write can leave a partial final line behind, skip anything we can't read
+            let applied = serde_json::from_str::<JournalEntry>(&line)
+                .map_err(Error::from)
+                .and_then(|entry| Self::apply(inner, entry));
+            if let Err(err) = applied {
+                println!(
+                    "Skipping journal line {} in {}: {}",
+                    line_number + 1,
+                    path.display(),
+                    err
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(inner: &mut MemoryStore, entry: JournalEntry) -> Result<()> {
+        match entry {
+            JournalEntry::ChannelSaved(channel) => inner.channel_save(channel),
+            JournalEntry::MessageSaved(message) => inner.message_save(message),
+            JournalEntry::MessageDeleted {
+                channel_id,
+                message_id,
+            } => inner.message_delete(channel_id, message_id).map(|_| ()),
+        }
+    }
+
+    // Rewrite the journal so it only holds the current state
+    fn compact(path: &Path, inner: &MemoryStore) -> Result<()> {
+        let mut compacted_path = PathBuf::from(path);
+        compacted_path.set_extension("compacting");
+
+        let mut compacted = File::create(&compacted_path)?;
+        for channel in inner.channels() {
+            Self::write_entry(&mut compacted, &JournalEntry::ChannelSaved(channel.clone()))?;
+            for message in channel.messages_iter() {
+                Self::write_entry(&mut compacted, &JournalEntry::MessageSaved(message.clone()))?;
+            }
+        }
+        compacted.sync_all()?;
+
+        fs::rename(&compacted_path, path)?;
+        Ok(())
+    }
+
+    fn write_entry(file: &mut File, entry: &JournalEntry) -> Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    fn append(&mut self, entry: &JournalEntry) -> Result<()> {
+        Self::write_entry(&mut self.journal, entry)
+    }
+}
+
+impl HoloStore for FileStore {
+    fn sessions(&self) -> Box<dyn Iterator<Item = &Session> + '_> {
+        self.inner.sessions()
+    }
+
+    fn session_get(&self, session_id: Uuid) -> Option<&Session> {
+        self.inner.session_get(session_id)
+    }
+
+    fn session_save(&mut self, session: Session) -> Result<()> {
+        self.inner.session_save(session)
+    }
+
+    fn session_remove(&mut self, session_id: Uuid) -> Result<Option<Session>> {
+        self.inner.session_remove(session_id)
+    }
+
+    fn channels(&self) -> Box<dyn Iterator<Item = &Channel> + '_> {
+        self.inner.channels()
+    }
+
+    fn channel_get(&self, channel_id: Uuid) -> Option<&Channel> {
+        self.inner.channel_get(channel_id)
+    }
+
+    fn channel_save(&mut self, channel: Channel) -> Result<()> {
+        self.append(&JournalEntry::ChannelSaved(channel.clone()))?;
+        self.inner.channel_save(channel)
+    }
+
+    fn message_save(&mut self, message: Message) -> Result<()> {
+        if self.inner.channel_get(message.channel_id).is_none() {
+            return Err(Error::System(format!(
+                "unknown channel {}",
+                message.channel_id
+            )));
+        }
+        self.append(&JournalEntry::MessageSaved(message.clone()))?;
+        self.inner.message_save(message)
+    }
+
+    fn message_delete(&mut self, channel_id: Uuid, message_id: Uuid) -> Result<Option<Message>> {
+        let exists = self
+            .inner
+            .channel_get(channel_id)
+            .and_then(|channel| channel.message_get_by_id(message_id))
+            .is_some();
+        if !exists {
+            return Ok(None);
+        }
+        self.append(&JournalEntry::MessageDeleted {
+            channel_id,
+            message_id,
+        })?;
+        self.inner.message_delete(channel_id, message_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    // Removed again when the test is done, whether it passed or not
+    struct TempJournal(PathBuf);
+
+    impl TempJournal {
+        fn new() -> Self {
+            TempJournal(std::env::temp_dir().join(format!("holonet-{}.jsonl", Uuid::new_v4())))
+        }
+
+        fn lines(&self) -> Vec<String> {
+            fs::read_to_string(&self.0)
+                .unwrap()
+                .lines()
+                .map(String::from)
+                .collect()
+        }
+    }
+
+    impl Drop for TempJournal {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn message(channel_id: Uuid, body: &str) -> Message {
+        Message::new(
+            Uuid::new_v4(),
+            channel_id,
+            Session::new(Uuid::new_v4(), "han"),
+            body,
+            Utc::now(),
+        )
+    }
+
+    #[test]
+    fn replays_channels_and_messages() {
+        let journal = TempJournal::new();
+        let channel = Channel::new(Uuid::nil(), "cantina", Uuid::nil());
+        let channel_id = channel.id;
+        let kept = message(channel_id, "kept");
+        let edited = message(channel_id, "before");
+        let deleted = message(channel_id, "deleted");
+        {
+            let mut store = FileStore::open(&journal.0).unwrap();
+            store.channel_save(channel).unwrap();
+            store.message_save(kept.clone()).unwrap();
+            store.message_save(edited.clone()).unwrap();
+            store.message_save(deleted.clone()).unwrap();
+            let mut edit = edited.clone();
+            edit.body = String::from("after");
+            store.message_save(edit).unwrap();
+            store.message_delete(channel_id, deleted.id).unwrap();
+        }
+
+        let store = FileStore::open(&journal.0).unwrap();
+        let channel = store.channel_get(channel_id).unwrap();
+        let bodies: Vec<&str> = channel
+            .messages_iter()
+            .map(|message| message.body.as_str())
+            .collect();
+        assert_eq!(bodies, vec!["kept", "after"]);
+        assert_eq!(channel.message_get_by_id(edited.id).unwrap().seq, 2);
+        assert!(channel.message_get_by_id(deleted.id).is_none());
+        // The deleted message's seq isn't handed out again
+        assert_eq!(channel.next_seq(), 4);
+    }
+
+    #[test]
+    fn compacts_to_current_state() {
+        let journal = TempJournal::new();
+        let channel = Channel::new(Uuid::nil(), "cantina", Uuid::nil());
+        let channel_id = channel.id;
+        let deleted = message(channel_id, "deleted");
+        {
+            let mut store = FileStore::open(&journal.0).unwrap();
+            store.channel_save(channel.clone()).unwrap();
+            store.channel_save(channel).unwrap();
+            store.message_save(message(channel_id, "kept")).unwrap();
+            store.message_save(deleted.clone()).unwrap();
+            store.message_delete(channel_id, deleted.id).unwrap();
+        }
+        assert_eq!(journal.lines().len(), 5);
+
+        FileStore::open(&journal.0).unwrap();
+        let lines = journal.lines();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("\"channel-saved\""));
+        assert!(lines[1].contains("\"kept\""));
+        assert!(!journal.0.with_extension("compacting").exists());
+    }
+
+    #[test]
+    fn skips_unreadable_lines() {
+        let journal = TempJournal::new();
+        let channel = Channel::new(Uuid::nil(), "cantina", Uuid::nil());
+        let channel_id = channel.id;
+        {
+            let mut store = FileStore::open(&journal.0).unwrap();
+            store.channel_save(channel).unwrap();
+            store.message_save(message(channel_id, "kept")).unwrap();
+        }
+        // A line cut short by a crash
+        let mut file = OpenOptions::new().append(true).open(&journal.0).unwrap();
+        file.write_all(b"{\"type\":\"message-sa").unwrap();
+
+        let store = FileStore::open(&journal.0).unwrap();
+        assert_eq!(
+            store
+                .channel_get(channel_id)
+                .unwrap()
+                .messages_iter()
+                .count(),
+            1
+        );
+        assert_eq!(journal.lines().len(), 2);
+    }
+
+    #[test]
+    fn keeps_sessions_out_of_the_journal() {
+        let journal = TempJournal::new();
+        let session = Session::new(Uuid::new_v4(), "chewie");
+        {
+            let mut store = FileStore::open(&journal.0).unwrap();
+            store.session_save(session.clone()).unwrap();
+            assert_eq!(store.session_get(session.id).unwrap().name, "chewie");
+            assert!(journal.lines().is_empty());
+        }
+
+        let store = FileStore::open(&journal.0).unwrap();
+        assert!(store.session_get(session.id).is_none());
+        assert_eq!(store.sessions().count(), 0);
+    }
+
+    #[test]
+    fn refuses_messages_for_unknown_channels() {
+        let journal = TempJournal::new();
+        let mut store = FileStore::open(&journal.0).unwrap();
+        assert!(store.message_save(message(Uuid::new_v4(), "lost")).is_err());
+        assert!(journal.lines().is_empty());
+    }
+}
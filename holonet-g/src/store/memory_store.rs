@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+use crate::holo::holo_errors::{Error, Result};
+use crate::model::channel::Channel;
+use crate::model::message::Message;
+use crate::model::session::Session;
+use crate::store::HoloStore;
+
+#[derive(Default)]
+pub struct MemoryStore {
+    sessions: HashMap<Uuid, Session>,
+    channels: Vec<Channel>,
+}
+
+impl MemoryStore {
+    fn channel_get_mut(&mut self, channel_id: Uuid) -> Option<&mut Channel> {
+        self.channels
+            .iter_mut()
+            .find(|channel| channel.id == channel_id)
+    }
+}
+
+impl HoloStore for MemoryStore {
+    fn sessions(&self) -> Box<dyn Iterator<Item = &Session> + '_> {
+        Box::new(self.sessions.values())
+    }
+
+    fn session_get(&self, session_id: Uuid) -> Option<&Session> {
+        self.sessions.get(&session_id)
+    }
+
+    fn session_save(&mut self, session: Session) -> Result<()> {
+        self.sessions.insert(session.id, session);
+        Ok(())
+    }
+
+    fn session_remove(&mut self, session_id: Uuid) -> Result<Option<Session>> {
+        Ok(self.sessions.remove(&session_id))
+    }
+
+    fn channels(&self) -> Box<dyn Iterator<Item = &Channel> + '_> {
+        Box::new(self.channels.iter())
+    }
+
+    fn channel_get(&self, channel_id: Uuid) -> Option<&Channel> {
+        self.channels
+            .iter()
+            .find(|channel| channel.id == channel_id)
+    }
+
+    fn channel_save(&mut self, mut channel: Channel) -> Result<()> {
+        match self.channel_get_mut(channel.id) {
+            Some(existing) => {
+                channel.messages = std::mem::take(&mut existing.messages);
+                channel.message_seqs = std::mem::take(&mut existing.message_seqs);
+                channel.last_seq = channel.last_seq.max(existing.last_seq);
+                *existing = channel;
+            }
+            None => self.channels.push(channel),
+        }
+        Ok(())
+    }
+
+    fn message_save(&mut self, message: Message) -> Result<()> {
+        let channel = self
+            .channel_get_mut(message.channel_id)
+            .ok_or_else(|| Error::System(format!("unknown channel {}", message.channel_id)))?;
+
+        channel.message_add(message);
+        Ok(())
+    }
+
+    fn message_delete(&mut self, channel_id: Uuid, message_id: Uuid) -> Result<Option<Message>> {
+        Ok(self
+            .channel_get_mut(channel_id)
+            .and_then(|channel| channel.message_delete_by_id(message_id)))
+    }
+}
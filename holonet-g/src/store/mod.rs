@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::holo::holo_errors::Result;
+use crate::model::channel::Channel;
+use crate::model::message::Message;
+use crate::model::session::Session;
+
+pub mod file_store;
+pub mod memory_store;
+
+use file_store::FileStore;
+use memory_store::MemoryStore;
+
+// Which backend the Holocaster keeps its sessions, channels and messages in
+// Sessions end with their connections, so every backend keeps them in memory only
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum StorageConfig {
+    // Everything is lost on restart
+    #[default]
+    Memory,
+    // Every change is appended to a journal file that is replayed on boot
+    File(PathBuf),
+}
+
+// Storage backend for the Holocaster
+// Writes return an error when the change could not be persisted, in which case nothing was changed
+// A write to the store, as shared between cluster nodes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload")]
+pub enum StoreChange {
+    #[serde(rename = "channel-saved")]
+    ChannelSaved(Channel),
+    #[serde(rename = "message-saved")]
+    MessageSaved(Message),
+    #[serde(rename = "message-deleted", rename_all = "camelCase")]
+    MessageDeleted { channel_id: Uuid, message_id: Uuid },
+}
+
+pub trait HoloStore: Send + Sync {
+    fn sessions(&self) -> Box<dyn Iterator<Item = &Session> + '_>;
+    fn session_get(&self, session_id: Uuid) -> Option<&Session>;
+    // Insert or replace a session
+    fn session_save(&mut self, session: Session) -> Result<()>;
+    fn session_remove(&mut self, session_id: Uuid) -> Result<Option<Session>>;
+
+    fn channels(&self) -> Box<dyn Iterator<Item = &Channel> + '_>;
+    fn channel_get(&self, channel_id: Uuid) -> Option<&Channel>;
+    // Insert or replace a channel, messages already stored in the channel are kept
+    fn channel_save(&mut self, channel: Channel) -> Result<()>;
+
+    // Insert or replace a message in the channel referenced by `message.channel_id`
+    fn message_save(&mut self, message: Message) -> Result<()>;
+    fn message_delete(&mut self, channel_id: Uuid, message_id: Uuid) -> Result<Option<Message>>;
+
+    // Apply a write made somewhere else, ex: by another cluster node
+    fn apply(&mut self, change: StoreChange) -> Result<()> {
+        match change {
+            StoreChange::ChannelSaved(channel) => self.channel_save(channel),
+            StoreChange::MessageSaved(message) => self.message_save(message),
+            StoreChange::MessageDeleted {
+                channel_id,
+                message_id,
+            } => self.message_delete(channel_id, message_id).map(|_| ()),
+        }
+    }
+}
+
+pub fn open(config: &StorageConfig) -> Result<Box<dyn HoloStore>> {
+    match config {
+        StorageConfig::Memory => Ok(Box::new(MemoryStore::default())),
+        StorageConfig::File(path) => Ok(Box::new(FileStore::open(path)?)),
+    }
+}